@@ -0,0 +1,71 @@
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// (path relative to the frontend directory, absolute path)
+fn collect(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    let entries = fs::read_dir(dir).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+    for entry in entries {
+        let entry = entry.expect("could not read frontend directory");
+        let name = entry
+            .file_name()
+            .into_string()
+            .expect("non UTF-8 file name");
+        let relative = format!("{}{}", prefix, name);
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, &format!("{}/", relative), files);
+        } else {
+            files.push((relative, path));
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBED_FRONTEND").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-env-changed=EMBED_FRONTEND_DIR");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let dir = match env::var_os("EMBED_FRONTEND_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../frontend"),
+    };
+    let dir = fs::canonicalize(&dir).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+    // a directory is scanned for changes as a whole
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut files = Vec::new();
+    collect(&dir, "", &mut files);
+    // looked up by binary search
+    files.sort();
+
+    // reproducible builds set this, so Last-Modified does not change
+    let built = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(secs) => secs.parse().expect("SOURCE_DATE_EPOCH is not a number"),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    };
+    let mut code = format!("pub const BUILT: i64 = {};\n\n", built);
+    code += "pub static FILES: &[EmbeddedFile] = &[\n";
+    for (relative, path) in &files {
+        let contents = fs::read(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let hash = Sha256::digest(&contents);
+        let etag: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        let _ = writeln!(
+            code,
+            "    EmbeddedFile {{ path: {:?}, contents: include_bytes!({:?}), etag: {:?} }},",
+            relative,
+            path,
+            format!("\"{}\"", etag)
+        );
+    }
+    code += "];\n";
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("frontend.rs");
+    fs::write(&out, code).unwrap_or_else(|e| panic!("{}: {}", out.display(), e));
+}
@@ -1,8 +1,9 @@
 use crate::authenticator;
 use crate::base64;
 use crate::note_db::{self, Note, NoteId};
+use crate::note_watch;
 use crate::{info, warn};
-use getrandom;
+use http::deferred::{PendingResponse, Reply};
 use http::server::HttpHandler;
 use http::types::{HttpRequest, HttpResponse, Method, StatusCode};
 use serde_json::{self, json};
@@ -10,7 +11,15 @@ use serde_json::{self, json};
 pub struct ApiHandler {}
 impl HttpHandler for ApiHandler {
     fn handle(&self, req: HttpRequest) -> HttpResponse {
-        return handle_api(req);
+        handle_api(req)
+    }
+
+    fn reply(&self, req: HttpRequest, pending: PendingResponse) -> Reply {
+        if req.path.split('/').nth(2) == Some("wait-for-change") {
+            return api_wait_for_change(req, pending);
+        }
+        pending.forget();
+        Reply::Now(handle_api(req))
     }
 }
 
@@ -22,6 +31,16 @@ fn not_authenticated() -> HttpResponse {
     HttpResponse::new(StatusCode::Unauthorized, None)
 }
 
+// an OK response with a JSON body
+pub fn json_response(body: Option<Vec<u8>>) -> HttpResponse {
+    let mut response = HttpResponse::new(StatusCode::OK, body);
+    response.fields.push((
+        "Content-Type".to_string(),
+        "application/json; charset=utf-8".to_string(),
+    ));
+    response
+}
+
 fn get_string(v: &serde_json::Value) -> Option<String> {
     match v {
         serde_json::Value::String(s) => Some(s.clone()),
@@ -34,7 +53,7 @@ fn get_id(v: &serde_json::Value) -> Option<NoteId> {
         serde_json::Value::Number(s) => s,
         _ => return None,
     };
-    return n.as_i64();
+    n.as_i64()
 }
 
 fn api_add_note(request: HttpRequest) -> HttpResponse {
@@ -61,6 +80,7 @@ fn api_add_note(request: HttpRequest) -> HttpResponse {
         None => return bad_request(),
     };
     let id = note_db::save(&Note::new(text.clone(), passkey));
+    note_watch::notify(passkey);
 
     info!("Stored note {}", id);
 
@@ -73,7 +93,7 @@ fn api_add_note(request: HttpRequest) -> HttpResponse {
         Some(s) => s,
         None => return HttpResponse::new(StatusCode::InternalError, None),
     };
-    HttpResponse::new(StatusCode::OK, Some(note.into_bytes()))
+    json_response(Some(note.into_bytes()))
 }
 
 fn stringify_note(entry: note_db::NoteEntry) -> Option<String> {
@@ -118,7 +138,7 @@ fn api_get_notes(request: HttpRequest) -> HttpResponse {
     }
     resp += "]";
 
-    HttpResponse::new(StatusCode::OK, Some(resp.into_bytes()))
+    json_response(Some(resp.into_bytes()))
 }
 
 fn api_delete_note(request: HttpRequest) -> HttpResponse {
@@ -146,10 +166,25 @@ fn api_delete_note(request: HttpRequest) -> HttpResponse {
         None => return bad_request(),
     };
     note_db::delete_if_user(&id, passkey);
+    note_watch::notify(passkey);
 
     HttpResponse::new(StatusCode::OK, None)
 }
 
+// long poll: answered when the caller's notes change, or after a timeout
+fn api_wait_for_change(request: HttpRequest, pending: PendingResponse) -> Reply {
+    let passkey = match authenticator::authenticate_request(&request) {
+        Ok((pk, _)) => pk,
+        Err(e) => {
+            warn!("authentication failed: {}", e);
+            pending.forget();
+            return Reply::Now(not_authenticated());
+        }
+    };
+    note_watch::wait(passkey, pending, note_watch::WAIT_TIMEOUT);
+    Reply::Later
+}
+
 fn api_create_account(request: HttpRequest) -> HttpResponse {
     if request.method != Method::Post {
         return bad_request();
@@ -184,7 +219,7 @@ fn api_create_account(request: HttpRequest) -> HttpResponse {
             None
         }
     };
-    HttpResponse::new(StatusCode::OK, body)
+    json_response(body)
 }
 
 fn api_who_am_i(request: HttpRequest) -> HttpResponse {
@@ -197,7 +232,7 @@ fn api_who_am_i(request: HttpRequest) -> HttpResponse {
             "authenticated": true,
             "username": info.1,
         }),
-        Err(e) => json!({
+        Err(_) => json!({
             "authenticated": false,
             "username": "",
         }),
@@ -209,17 +244,36 @@ fn api_who_am_i(request: HttpRequest) -> HttpResponse {
             None
         }
     };
-    HttpResponse::new(StatusCode::OK, body)
+    json_response(body)
 }
 
 fn hello_world() -> HttpResponse {
-    HttpResponse::new(StatusCode::OK, Some("hello world!".as_bytes().to_vec()))
+    let mut response = HttpResponse::new(StatusCode::OK, Some("hello world!".as_bytes().to_vec()));
+    response.fields.push((
+        "Content-Type".to_string(),
+        "text/plain; charset=utf-8".to_string(),
+    ));
+    response
+}
+
+/// The endpoint a request is for, as a metrics label.
+pub fn route(request: &HttpRequest) -> &'static str {
+    match request.path.split('/').nth(2) {
+        Some("add-note") => "/api/add-note",
+        Some("get-notes") => "/api/get-notes",
+        Some("delete-note") => "/api/delete-note",
+        Some("create-account") => "/api/create-account",
+        Some("who-am-i") => "/api/who-am-i",
+        Some("hello") => "/api/hello",
+        Some("wait-for-change") => "/api/wait-for-change",
+        _ => "/api/other",
+    }
 }
 
 fn handle_api(request: HttpRequest) -> HttpResponse {
     let path: Vec<&str> = request.path.split('/').collect();
 
-    return match path[2] {
+    match path[2] {
         "add-note" => api_add_note(request),
         "get-notes" => api_get_notes(request),
         "delete-note" => api_delete_note(request),
@@ -228,5 +282,32 @@ fn handle_api(request: HttpRequest) -> HttpResponse {
         "hello" => hello_world(),
         "not-implemented" => HttpResponse::new(StatusCode::NotImplemented, None),
         _ => HttpResponse::new(StatusCode::NotFound, None),
-    };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::compression;
+    use http::CompressionConfig;
+
+    #[test]
+    fn test_json_compressed() {
+        let notes: Vec<serde_json::Value> = (0..50)
+            .map(|id| json!({"text": "a note", "id": id, "date": 1700000000}))
+            .collect();
+        let body = serde_json::to_string(&notes).unwrap().into_bytes();
+        let mut response = json_response(Some(body.clone()));
+        let config = CompressionConfig {
+            min_size: 1024,
+            level: 6,
+        };
+        compression::compress(&mut response, Some("gzip, deflate"), &config);
+        assert_eq!(response.field("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.field("Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+        assert!(response.body.unwrap().len() < body.len());
+    }
 }
@@ -1,7 +1,7 @@
 use crate::base64;
-use crate::note_db::{self, UserId};
+use crate::note_db;
 use crate::{info, warn};
-use http::types::{HttpRequest, HttpResponse, Method, StatusCode};
+use http::types::HttpRequest;
 use std::fmt;
 use std::hash::{self, Hash, Hasher};
 
@@ -19,11 +19,9 @@ impl fmt::Display for AuthenticationError {
 }
 
 pub fn generate_passkey() -> (Vec<u8>, i64) {
-    let mut vec = Vec::new();
-    vec.resize(64, 0);
-    match getrandom::fill(vec.as_mut_slice()) {
-        Err(e) => panic!("source of randomness failed: {}", e),
-        Ok(()) => {}
+    let mut vec = vec![0; 64];
+    if let Err(e) = getrandom::fill(vec.as_mut_slice()) {
+        panic!("source of randomness failed: {}", e)
     };
 
     let hash = generate_hash(&vec);
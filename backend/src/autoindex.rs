@@ -0,0 +1,147 @@
+use http::date::DateTime;
+use serde_json::json;
+use std::time::SystemTime;
+
+/// One file or directory in a listing.
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Directories first, then by name.
+pub fn sort(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name)));
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// a file name as one path segment of a link
+fn encode_segment(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+/// A page for browsers; `path` is the directory's request path.
+pub fn html(path: &str, entries: &[Entry]) -> String {
+    let title = format!("Index of {}", escape_html(path));
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title, title
+    );
+    if path != "/" {
+        page += "<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n";
+    }
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        let size = if entry.dir {
+            "-".to_string()
+        } else {
+            size(entry.size)
+        };
+        page += &format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            encode_segment(&entry.name),
+            slash,
+            escape_html(&entry.name),
+            slash,
+            size,
+            DateTime::from_system_time(entry.modified).http_date()
+        );
+    }
+    page += "</table>\n</body>\n</html>\n";
+    page
+}
+
+/// The listing as a JSON array, for scripts.
+pub fn json(entries: &[Entry]) -> String {
+    let entries: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "type": if entry.dir { "directory" } else { "file" },
+                "size": entry.size,
+                "modified": DateTime::from_system_time(entry.modified).rfc3339(),
+            })
+        })
+        .collect();
+    serde_json::Value::Array(entries).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(name: &str, dir: bool, size: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            dir,
+            size,
+            modified: UNIX_EPOCH + Duration::from_secs(971186136),
+        }
+    }
+
+    #[test]
+    fn test_listing() {
+        let mut entries = vec![
+            entry("b.mp3", false, 3 << 20),
+            entry("<a> & b.txt", false, 12),
+            entry("old", true, 4096),
+        ];
+        sort(&mut entries);
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["old", "<a> & b.txt", "b.mp3"]);
+
+        let page = html("/downloads/", &entries);
+        assert!(page.contains("<title>Index of /downloads/</title>"));
+        assert!(page.contains("<a href=\"old/\">old/</a></td><td>-</td>"));
+        assert!(page.contains(
+            "<a href=\"%3Ca%3E%20%26%20b.txt\">&lt;a&gt; &amp; b.txt</a></td><td>12</td>"
+        ));
+        assert!(page.contains("<td>3.0M</td><td>Tue, 10 Oct 2000 13:55:36 GMT</td>"));
+        assert!(page.contains("href=\"../\""));
+
+        let listing: serde_json::Value = serde_json::from_str(&json(&entries)).unwrap();
+        assert_eq!(listing[0]["type"], "directory");
+        assert_eq!(listing[1]["name"], "<a> & b.txt");
+        assert_eq!(listing[2]["size"], 3 << 20);
+        assert_eq!(listing[2]["modified"], "2000-10-10T13:55:36Z");
+    }
+}
@@ -12,16 +12,16 @@ enum Decoded {
 }
 fn reverse(c: char) -> Decoded {
     let cu = c as u8;
-    if cu >= 'A' as u8 && cu <= 'Z' as u8 {
-        return Decoded::Ok(cu - ('A' as u8));
+    if cu.is_ascii_uppercase() {
+        return Decoded::Ok(cu - b'A');
     }
 
-    if cu >= 'a' as u8 && cu <= 'z' as u8 {
-        return Decoded::Ok(cu - ('a' as u8) + 26);
+    if cu.is_ascii_lowercase() {
+        return Decoded::Ok(cu - b'a' + 26);
     }
 
-    if cu >= '0' as u8 && cu <= '9' as u8 {
-        return Decoded::Ok(cu - ('0' as u8) + 52);
+    if cu.is_ascii_digit() {
+        return Decoded::Ok(cu - b'0' + 52);
     }
     match c {
         '+' => Decoded::Ok(62),
@@ -34,11 +34,7 @@ fn reverse(c: char) -> Decoded {
 pub fn encode(bytes: &[u8]) -> String {
     let mut s = String::new();
     let mut bytes = bytes.iter();
-    loop {
-        let b1 = match bytes.next() {
-            Some(b) => b,
-            None => break,
-        };
+    while let Some(b1) = bytes.next() {
         let bits = b1 >> 2;
         s.push(index(bits));
 
@@ -82,7 +78,7 @@ pub enum DecodeError {
 
 impl std::fmt::Display for DecodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        todo!()
+        write!(f, "invalid base64")
     }
 }
 impl std::error::Error for DecodeError {}
@@ -90,11 +86,7 @@ impl std::error::Error for DecodeError {}
 pub fn decode(string: &str) -> Result<Vec<u8>, DecodeError> {
     let mut ret = Vec::new();
     let mut string = string.chars();
-    loop {
-        let c = match string.next() {
-            Some(c) => c,
-            None => break,
-        };
+    while let Some(c) = string.next() {
         let i1 = match reverse(c) {
             Decoded::Ok(i) => i,
             Decoded::Padding => break,
@@ -136,12 +128,13 @@ pub fn decode(string: &str) -> Result<Vec<u8>, DecodeError> {
             Decoded::Fault => return Err(DecodeError::SkillIssue),
         };
 
-        ret.push((i3 << 6) | (i4 >> 0));
+        ret.push((i3 << 6) | i4);
     }
     Ok(ret)
 }
 
 #[cfg(test)]
+#[allow(duplicate_macro_attributes, clippy::unnecessary_to_owned)]
 mod tests {
     use super::*;
     #[test]
@@ -1,4 +1,18 @@
-use http::{ServerConfig, TlsConfig};
+use crate::embedded;
+use crate::file_cache::CacheConfig;
+use crate::my_logger::{
+    FileOutput, LogConfig, LogFormat as LogLineFormat, LogOutput, RotateInterval,
+};
+use crate::sites;
+use crate::static_files::StaticConfig;
+use http::access_log::LogFormat;
+use http::forwarded::Cidr;
+use http::{
+    AccessLogConfig, ClientAuthConfig, ClientAuthMode, CompressionConfig, ServerConfig,
+    SessionConfig, SniCertificate, TlsConfig, TlsVersion,
+};
+use http::{ConnectionLimits, OverloadPolicy, RateLimitConfig, RateLimitKey, RouteLimit};
+use log::LevelFilter;
 use serde_json::Value;
 use std::error::Error;
 use std::fmt;
@@ -8,10 +22,23 @@ use std::fs;
 pub enum ParseError {
     Missing(String),
     MissingTls(String),
+    Invalid(String, String),
     Syntax(Box<dyn Error>),
 }
 impl Error for ParseError {}
 
+impl ParseError {
+    // names the key by its full path, for errors found in a nested object
+    fn within(self, prefix: &str) -> ParseError {
+        use ParseError::*;
+        match self {
+            Missing(s) => Missing(format!("{}.{}", prefix, s)),
+            Invalid(s, why) => Invalid(format!("{}.{}", prefix, s), why),
+            e => e,
+        }
+    }
+}
+
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use ParseError::*;
@@ -22,6 +49,7 @@ impl fmt::Display for ParseError {
                 "missing `{}` for tls configuration (or add `allow_insecure` = true)",
                 s
             ),
+            Invalid(s, why) => write!(f, "invalid `{}` in json config: {}", s, why),
             Syntax(e) => write!(f, "syntax error: {}", e),
         }
     }
@@ -29,9 +57,40 @@ impl fmt::Display for ParseError {
 
 #[derive(Debug)]
 pub struct Config {
-    pub frontend_dir: String,
+    /// optional when serving the embedded frontend, to override its files
+    pub frontend_dir: Option<String>,
     pub database: String,
     pub http: ServerConfig,
+    /// generate a self-signed certificate when `cert` and `key` do not exist
+    pub dev_cert: bool,
+    pub dev_hostnames: Vec<String>,
+    pub rate_limits: RateLimitConfig,
+    pub metrics: Option<MetricsConfig>,
+    pub log: LogConfig,
+    pub static_files: StaticConfig,
+    /// serve `/api` on the default host
+    pub api: bool,
+    /// sites on other names; the settings above are for any other name
+    pub hosts: Vec<VirtualHost>,
+}
+
+#[derive(Debug)]
+pub struct VirtualHost {
+    /// matched case-insensitively against `Host`, or SNI without it
+    pub names: Vec<String>,
+    pub frontend_dir: Option<String>,
+    pub api: bool,
+    pub static_files: StaticConfig,
+}
+
+#[derive(Debug)]
+pub struct MetricsConfig {
+    pub path: String,
+    /// serve metrics on a plaintext listener here instead of the main one
+    pub address: Option<String>,
+    /// on the main listener, serve metrics to everyone rather than only to
+    /// loopback clients
+    pub public: bool,
 }
 
 fn get_string(cfg: &serde_json::Value, name: &str) -> Result<String, ParseError> {
@@ -42,17 +101,528 @@ fn get_string(cfg: &serde_json::Value, name: &str) -> Result<String, ParseError>
     }
 }
 
+fn invalid(name: &str, why: &str) -> ParseError {
+    ParseError::Invalid(name.to_string(), why.to_string())
+}
+
+fn get_optional_string(cfg: &serde_json::Value, name: &str) -> Result<Option<String>, ParseError> {
+    match &cfg[name] {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s.clone())),
+        _ => Err(invalid(name, "expected a string")),
+    }
+}
+
+fn get_string_list(cfg: &serde_json::Value, name: &str) -> Result<Vec<String>, ParseError> {
+    let list = match &cfg[name] {
+        Value::Null => return Ok(Vec::new()),
+        Value::Array(a) => a,
+        _ => return Err(invalid(name, "expected a list of strings")),
+    };
+    let mut strings = Vec::new();
+    for v in list {
+        match v {
+            Value::String(s) => strings.push(s.clone()),
+            _ => return Err(invalid(name, "expected a list of strings")),
+        }
+    }
+    Ok(strings)
+}
+
+fn get_bool(cfg: &serde_json::Value, name: &str, default: bool) -> Result<bool, ParseError> {
+    match &cfg[name] {
+        Value::Null => Ok(default),
+        Value::Bool(b) => Ok(*b),
+        _ => Err(invalid(name, "expected true or false")),
+    }
+}
+
+fn get_usize(cfg: &serde_json::Value, name: &str, default: usize) -> Result<usize, ParseError> {
+    match &cfg[name] {
+        Value::Null => Ok(default),
+        Value::Number(n) => match n.as_u64() {
+            Some(n) => Ok(n as usize),
+            None => Err(invalid(name, "expected a non-negative integer")),
+        },
+        _ => Err(invalid(name, "expected a non-negative integer")),
+    }
+}
+
+fn get_cidr_list(cfg: &serde_json::Value, name: &str) -> Result<Vec<Cidr>, ParseError> {
+    let mut cidrs = Vec::new();
+    for s in get_string_list(cfg, name)? {
+        match s.parse() {
+            Ok(c) => cidrs.push(c),
+            Err(e) => return Err(invalid(name, &format!("`{}`: {}", s, e))),
+        }
+    }
+    Ok(cidrs)
+}
+
+fn get_number(cfg: &serde_json::Value, name: &str) -> Result<f64, ParseError> {
+    match &cfg[name] {
+        Value::Number(n) => Ok(n.as_f64().unwrap_or(0.0)),
+        Value::Null => Err(ParseError::Missing(name.to_string())),
+        _ => Err(invalid(name, "expected a number")),
+    }
+}
+
+fn get_route_limit(cfg: &serde_json::Value) -> Result<RouteLimit, ParseError> {
+    let per_minute = get_number(cfg, "per_minute")?;
+    if per_minute <= 0.0 {
+        return Err(invalid("per_minute", "must be positive"));
+    }
+    let key = match get_optional_string(cfg, "key")?.as_deref() {
+        None | Some("ip") => RateLimitKey::ClientAddr,
+        Some("user") => RateLimitKey::Custom,
+        Some(_) => return Err(invalid("key", "expected \"ip\" or \"user\"")),
+    };
+    Ok(RouteLimit {
+        path: get_string(cfg, "path")?,
+        burst: get_usize(cfg, "burst", 1)?.max(1) as u32,
+        per_second: per_minute / 60.0,
+        key,
+    })
+}
+
+fn get_rate_limits(cfg: &serde_json::Value) -> Result<RateLimitConfig, ParseError> {
+    let limits = &cfg["rate_limits"];
+    let routes = match &limits["routes"] {
+        Value::Null => Vec::new(),
+        Value::Array(routes) => routes
+            .iter()
+            .enumerate()
+            .map(|(i, route)| {
+                get_route_limit(route).map_err(|e| e.within(&format!("rate_limits.routes.{}", i)))
+            })
+            .collect::<Result<Vec<RouteLimit>, ParseError>>()?,
+        _ => return Err(invalid("rate_limits.routes", "expected a list")),
+    };
+    Ok(RateLimitConfig {
+        routes,
+        max_clients: get_usize(limits, "max_clients", 10000)?.max(1),
+    })
+}
+
+fn get_connection_limits(cfg: &serde_json::Value) -> Result<ConnectionLimits, ParseError> {
+    let policy = match get_optional_string(cfg, "overload_policy")?.as_deref() {
+        None | Some("stop_accepting") => OverloadPolicy::StopAccepting,
+        Some("reject") => OverloadPolicy::Reject,
+        Some(_) => {
+            return Err(invalid(
+                "overload_policy",
+                "expected \"stop_accepting\" or \"reject\"",
+            ))
+        }
+    };
+    Ok(ConnectionLimits {
+        max_connections: get_usize(cfg, "max_connections", 1024)?,
+        max_per_ip: get_usize(cfg, "max_connections_per_ip", 0)?,
+        policy,
+    })
+}
+
+fn get_access_log(cfg: &serde_json::Value) -> Result<Option<AccessLogConfig>, ParseError> {
+    let log = &cfg["access_log"];
+    if log.is_null() {
+        return Ok(None);
+    }
+    let path = match get_string(log, "path") {
+        Ok(p) => p,
+        Err(_) => return Err(ParseError::Missing("access_log.path".to_string())),
+    };
+    let format = get_optional_string(log, "format")?.unwrap_or("combined".to_string());
+    let format: LogFormat = match format.parse() {
+        Ok(f) => f,
+        Err(e) => return Err(invalid("access_log.format", &e)),
+    };
+    Ok(Some(AccessLogConfig { path, format }))
+}
+
+fn get_compression(cfg: &serde_json::Value) -> Result<Option<CompressionConfig>, ParseError> {
+    let compression = &cfg["compression"];
+    if !get_bool(compression, "enabled", true)? {
+        return Ok(None);
+    }
+    let level = get_usize(compression, "level", 6)?;
+    if level > 9 {
+        return Err(invalid("compression.level", "expected 0 to 9"));
+    }
+    Ok(Some(CompressionConfig {
+        min_size: get_usize(compression, "min_size", 1024)?,
+        level: level as u32,
+    }))
+}
+
+fn get_metrics(cfg: &serde_json::Value) -> Result<Option<MetricsConfig>, ParseError> {
+    let metrics = &cfg["metrics"];
+    if metrics.is_null() {
+        return Ok(None);
+    }
+    let path = get_optional_string(metrics, "path")?.unwrap_or("/metrics".to_string());
+    if !path.starts_with('/') {
+        return Err(invalid("metrics.path", "must start with `/`"));
+    }
+    Ok(Some(MetricsConfig {
+        path,
+        address: get_optional_string(metrics, "address")?,
+        public: get_bool(metrics, "public", false)?,
+    }))
+}
+
+fn get_level(cfg: &serde_json::Value, name: &str) -> Result<Option<LevelFilter>, ParseError> {
+    match get_optional_string(cfg, name)? {
+        None => Ok(None),
+        Some(s) => match s.parse() {
+            Ok(level) => Ok(Some(level)),
+            Err(_) => Err(invalid(
+                name,
+                "expected one of off, error, warn, info, debug, trace",
+            )),
+        },
+    }
+}
+
+fn get_log(cfg: &serde_json::Value) -> Result<LogConfig, ParseError> {
+    let log = &cfg["log"];
+    let default = LogConfig::default();
+    let mut modules = Vec::new();
+    match &log["modules"] {
+        Value::Null => {}
+        Value::Object(m) => {
+            for module in m.keys() {
+                let level =
+                    get_level(&log["modules"], module).map_err(|e| e.within("log.modules"))?;
+                if let Some(level) = level {
+                    modules.push((module.clone(), level));
+                }
+            }
+        }
+        _ => return Err(invalid("log.modules", "expected an object")),
+    }
+    let format = match get_optional_string(log, "format")?.as_deref() {
+        None | Some("text") => LogLineFormat::Text,
+        Some("json") => LogLineFormat::Json,
+        Some(_) => return Err(invalid("log.format", "expected \"text\" or \"json\"")),
+    };
+    let output = match get_optional_string(log, "output")?.as_deref() {
+        None | Some("stdout") => LogOutput::Stdout,
+        Some("stderr") => LogOutput::Stderr,
+        Some("file") => {
+            let path = match get_string(log, "file") {
+                Ok(p) => p,
+                Err(_) => return Err(ParseError::Missing("log.file".to_string())),
+            };
+            let interval = match get_optional_string(log, "rotate_interval")?.as_deref() {
+                None | Some("never") => None,
+                Some("hourly") => Some(RotateInterval::Hourly),
+                Some("daily") => Some(RotateInterval::Daily),
+                Some(_) => {
+                    return Err(invalid(
+                        "log.rotate_interval",
+                        "expected \"never\", \"hourly\" or \"daily\"",
+                    ))
+                }
+            };
+            LogOutput::File(FileOutput {
+                path,
+                max_bytes: get_usize(log, "rotate_bytes", 0)? as u64,
+                interval,
+                keep: get_usize(log, "keep", 7)?,
+            })
+        }
+        Some(_) => {
+            return Err(invalid(
+                "log.output",
+                "expected \"stdout\", \"stderr\" or \"file\"",
+            ))
+        }
+    };
+    Ok(LogConfig {
+        level: get_level(log, "level")
+            .map_err(|e| e.within("log"))?
+            .unwrap_or(default.level),
+        modules,
+        format,
+        timestamps: get_bool(log, "timestamps", default.timestamps)?,
+        output,
+    })
+}
+
+fn get_file_cache(files: &serde_json::Value) -> Result<Option<CacheConfig>, ParseError> {
+    let cache = &files["cache"];
+    if cache.is_null() || !get_bool(cache, "enabled", true)? {
+        return Ok(None);
+    }
+    Ok(Some(CacheConfig {
+        max_bytes: get_usize(cache, "max_bytes", 32 << 20)?,
+        max_file_bytes: get_usize(cache, "max_file_bytes", 1 << 20)?,
+    }))
+}
+
+fn get_static(cfg: &serde_json::Value) -> Result<StaticConfig, ParseError> {
+    let files = &cfg["static"];
+    let mut mime_types = Vec::new();
+    match &files["mime_types"] {
+        Value::Null => {}
+        Value::Object(m) => {
+            for (ext, mime) in m {
+                let ext = ext.strip_prefix('.').unwrap_or(ext);
+                match mime {
+                    Value::String(mime) if mime.contains('/') => {
+                        mime_types.push((ext.to_string(), mime.clone()))
+                    }
+                    _ => {
+                        return Err(invalid(
+                            &format!("static.mime_types.{}", ext),
+                            "expected a MIME type like \"text/plain\"",
+                        ))
+                    }
+                }
+            }
+        }
+        _ => return Err(invalid("static.mime_types", "expected an object")),
+    }
+    let mut cache_control = Vec::new();
+    match &files["cache_control"] {
+        Value::Null => {}
+        Value::Array(rules) => {
+            for rule in rules {
+                let pattern = get_string(rule, "path");
+                let value = get_string(rule, "value");
+                match (pattern, value) {
+                    (Ok(p), Ok(v)) => cache_control.push((p, v)),
+                    _ => {
+                        return Err(invalid(
+                            "static.cache_control",
+                            "expected a list of {\"path\", \"value\"} objects",
+                        ))
+                    }
+                }
+            }
+        }
+        _ => return Err(invalid("static.cache_control", "expected a list")),
+    }
+    let embedded = match get_optional_string(files, "source")?.as_deref() {
+        None | Some("disk") => false,
+        Some("embedded") if embedded::AVAILABLE => true,
+        Some("embedded") => {
+            return Err(invalid(
+                "static.source",
+                "this build has no embedded frontend (feature `embed-frontend`)",
+            ))
+        }
+        Some(_) => {
+            return Err(invalid(
+                "static.source",
+                "expected \"disk\" or \"embedded\"",
+            ))
+        }
+    };
+    let mut error_pages = Vec::new();
+    match &files["error_pages"] {
+        Value::Null => {}
+        Value::Object(m) => {
+            for (code, page) in m {
+                let name = format!("static.error_pages.{}", code);
+                let code = match code.parse::<u16>() {
+                    Ok(c) if (400..600).contains(&c) => c,
+                    _ => return Err(invalid(&name, "expected a status code from 400 to 599")),
+                };
+                match page {
+                    Value::String(page) => error_pages.push((code, page.clone())),
+                    _ => return Err(invalid(&name, "expected a file name")),
+                }
+            }
+        }
+        _ => return Err(invalid("static.error_pages", "expected an object")),
+    }
+    let symlinks_outside_root = get_bool(files, "symlinks_outside_root", false)?;
+    let cache = get_file_cache(files)?;
+    // changes behind a symlink out of the root are not watched
+    if symlinks_outside_root && cache.is_some() {
+        return Err(invalid(
+            "static.cache",
+            "cannot be enabled with `symlinks_outside_root`",
+        ));
+    }
+    Ok(StaticConfig {
+        mime_types,
+        error_pages,
+        fallback: get_optional_string(files, "fallback")?,
+        autoindex: get_string_list(files, "autoindex")?,
+        cache_control,
+        dotfiles: get_bool(files, "dotfiles", false)?,
+        symlinks_outside_root,
+        precompressed: get_bool(files, "precompressed", true)?,
+        cache,
+        embedded,
+    })
+}
+
+fn get_frontend_dir(
+    cfg: &serde_json::Value,
+    static_files: &StaticConfig,
+) -> Result<Option<String>, ParseError> {
+    let frontend_dir = get_optional_string(cfg, "frontend_dir")?;
+    if frontend_dir.is_none() && !static_files.embedded {
+        return Err(ParseError::Missing("frontend_dir".to_string()));
+    }
+    Ok(frontend_dir)
+}
+
+// the top-level `static` settings, with those of `host` replacing them key
+// by key
+fn host_static(cfg: &serde_json::Value, host: &serde_json::Value) -> Result<Value, ParseError> {
+    let mut files = match &cfg["static"] {
+        Value::Null => serde_json::Map::new(),
+        Value::Object(m) => m.clone(),
+        _ => return Err(invalid("static", "expected an object")),
+    };
+    match &host["static"] {
+        Value::Null => {}
+        Value::Object(m) => files.extend(m.clone()),
+        _ => return Err(invalid("hosts.static", "expected an object")),
+    }
+    Ok(serde_json::json!({ "static": files }))
+}
+
+fn get_hosts(
+    cfg: &serde_json::Value,
+    mut tls: Option<&mut TlsConfig>,
+) -> Result<Vec<VirtualHost>, ParseError> {
+    let list = match &cfg["hosts"] {
+        Value::Null => return Ok(Vec::new()),
+        Value::Array(a) => a,
+        _ => return Err(invalid("hosts", "expected a list")),
+    };
+    let mut hosts: Vec<VirtualHost> = Vec::new();
+    for host in list {
+        // as they are matched, so the certificates and sites agree
+        let names: Vec<String> = get_string_list(host, "names")?
+            .iter()
+            .map(|name| sites::host_name(name))
+            .collect();
+        if names.is_empty() {
+            return Err(invalid("hosts.names", "expected at least one name"));
+        }
+        for (i, name) in names.iter().enumerate() {
+            let taken = hosts
+                .iter()
+                .flat_map(|h| &h.names)
+                .chain(&names[..i])
+                .any(|n| n == name);
+            if taken {
+                return Err(invalid(
+                    "hosts.names",
+                    &format!("`{}` is used by more than one host", name),
+                ));
+            }
+        }
+        let cert = get_optional_string(host, "cert")?;
+        let key = get_optional_string(host, "key")?;
+        match (cert, key, tls.as_deref_mut()) {
+            (None, None, _) => {}
+            (Some(cert), Some(key), Some(tls)) => tls.sni.push(SniCertificate {
+                names: names.clone(),
+                cert,
+                key,
+            }),
+            (Some(_), Some(_), None) => {
+                return Err(invalid("hosts.cert", "tls is not enabled"));
+            }
+            (Some(_), None, _) => return Err(ParseError::Missing("hosts.key".to_string())),
+            (None, Some(_), _) => return Err(ParseError::Missing("hosts.cert".to_string())),
+        }
+        let static_files = get_static(&host_static(cfg, host)?)?;
+        hosts.push(VirtualHost {
+            names,
+            frontend_dir: get_frontend_dir(host, &static_files)?,
+            api: get_bool(host, "api", false)?,
+            static_files,
+        });
+    }
+    Ok(hosts)
+}
+
 fn get_tls(cfg: &serde_json::Value) -> Result<TlsConfig, ParseError> {
     use ParseError::*;
-    let cert = match get_string(&cfg, "cert") {
+    let cert = match get_string(cfg, "cert") {
         Err(Missing(s)) => Err(MissingTls(s)),
         a => a,
     }?;
-    let key = match get_string(&cfg, "key") {
+    let key = match get_string(cfg, "key") {
         Err(Missing(s)) => Err(MissingTls(s)),
         a => a,
     }?;
-    Ok(TlsConfig { cert, key })
+    let min_version = match get_optional_string(cfg, "tls_min_version")?.as_deref() {
+        None | Some("1.2") => TlsVersion::Tls12,
+        Some("1.3") => TlsVersion::Tls13,
+        Some(_) => return Err(invalid("tls_min_version", "expected \"1.2\" or \"1.3\"")),
+    };
+    let cipher_suites = get_string_list(cfg, "cipher_suites")?;
+    let mut usable_suites = 0;
+    for name in &cipher_suites {
+        match http::tls::cipher_suite_version(name) {
+            Some(v) if v >= min_version => usable_suites += 1,
+            Some(_) => {}
+            None => {
+                return Err(invalid(
+                    "cipher_suites",
+                    &format!("unknown cipher suite `{}`", name),
+                ))
+            }
+        }
+    }
+    if !cipher_suites.is_empty() && usable_suites == 0 {
+        return Err(invalid(
+            "cipher_suites",
+            "no cipher suite is usable with `tls_min_version`",
+        ));
+    }
+    let alpn_protocols = get_string_list(cfg, "alpn")?;
+    for protocol in &alpn_protocols {
+        // only advertise protocols the server can actually speak
+        if protocol != "http/1.1" && protocol != "http/1.0" {
+            return Err(invalid(
+                "alpn",
+                &format!("unsupported protocol `{}`", protocol),
+            ));
+        }
+    }
+    let session = SessionConfig {
+        tickets: get_bool(cfg, "session_tickets", true)?,
+        cache_size: get_usize(cfg, "session_cache_size", 256)?,
+    };
+    Ok(TlsConfig {
+        cert,
+        key,
+        sni: Vec::new(),
+        client_auth: get_client_auth(cfg)?,
+        min_version,
+        cipher_suites,
+        alpn_protocols,
+        session,
+        key_log: get_optional_string(cfg, "key_log")?,
+    })
+}
+
+fn get_client_auth(cfg: &serde_json::Value) -> Result<Option<ClientAuthConfig>, ParseError> {
+    let Some(ca) = get_optional_string(cfg, "client_ca")? else {
+        return Ok(None);
+    };
+    let mode = match &cfg["client_auth"] {
+        Value::Null => ClientAuthMode::Required,
+        Value::String(s) if s == "required" => ClientAuthMode::Required,
+        Value::String(s) if s == "optional" => ClientAuthMode::Optional,
+        _ => {
+            return Err(invalid(
+                "client_auth",
+                "expected \"required\" or \"optional\"",
+            ))
+        }
+    };
+    Ok(Some(ClientAuthConfig { ca, mode }))
 }
 
 pub fn parse_config_file(fp: &String) -> Result<Config, ParseError> {
@@ -71,22 +641,38 @@ pub fn parse_config_file(fp: &String) -> Result<Config, ParseError> {
         }
     };
 
-    let allow_insecure = match &cfg["allow_insecure"] {
-        serde_json::Value::Bool(true) => true,
-        _ => false,
-    };
+    let allow_insecure = matches!(&cfg["allow_insecure"], serde_json::Value::Bool(true));
     let tls = if allow_insecure {
-        get_tls(&cfg).ok()
+        match get_tls(&cfg) {
+            Err(ParseError::MissingTls(_)) => None,
+            r => Some(r?),
+        }
     } else {
         Some(get_tls(&cfg)?)
     };
-    let http = ServerConfig {
+    let mut http = ServerConfig {
         address: get_string(&cfg, "address")?,
         tls,
+        proxy_protocol: get_bool(&cfg, "proxy_protocol", false)?,
+        trusted_proxies: get_cidr_list(&cfg, "trusted_proxies")?,
+        limits: get_connection_limits(&cfg)?,
+        access_log: get_access_log(&cfg)?,
+        compression: get_compression(&cfg)?,
     };
-    return Ok(Config {
-        frontend_dir: get_string(&cfg, "frontend_dir")?,
+    let static_files = get_static(&cfg)?;
+    let frontend_dir = get_frontend_dir(&cfg, &static_files)?;
+    let hosts = get_hosts(&cfg, http.tls.as_mut())?;
+    Ok(Config {
+        frontend_dir,
         database: get_string(&cfg, "database")?,
         http,
-    });
+        dev_cert: get_bool(&cfg, "dev_cert", false)?,
+        dev_hostnames: get_string_list(&cfg, "dev_hostnames")?,
+        rate_limits: get_rate_limits(&cfg)?,
+        metrics: get_metrics(&cfg)?,
+        log: get_log(&cfg)?,
+        static_files,
+        api: get_bool(&cfg, "api", true)?,
+        hosts,
+    })
 }
@@ -0,0 +1,84 @@
+use crate::{info, warn};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+const LOCAL_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+fn write_file(fp: &str, contents: &[u8], mode: u32) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = Path::new(fp).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(fp)?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// Generates a self-signed certificate for local development if neither
+/// `cert` nor `key` exists. The files are left on disk so the browser
+/// exception only has to be added once; delete both to regenerate.
+pub fn ensure(cert: &str, key: &str, hostnames: &[String]) -> Result<(), Box<dyn Error>> {
+    match (Path::new(cert).exists(), Path::new(key).exists()) {
+        (true, true) => {
+            info!("using development certificate `{}`", cert);
+            return Ok(());
+        }
+        (false, false) => {}
+        // likely a typo in one path; never overwrite what is there
+        (true, false) => {
+            return Err(format!("`{}` exists but `{}` does not", cert, key).into());
+        }
+        (false, true) => {
+            return Err(format!("`{}` exists but `{}` does not", key, cert).into());
+        }
+    }
+
+    let mut names: Vec<String> = LOCAL_NAMES.iter().map(|s| s.to_string()).collect();
+    for name in hostnames {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    let generated = rcgen::generate_simple_self_signed(names.clone())?;
+    write_file(cert, generated.cert.pem().as_bytes(), 0o644)?;
+    write_file(key, generated.signing_key.serialize_pem().as_bytes(), 0o600)?;
+    warn!(
+        "generated self-signed development certificate `{}` for {}",
+        cert,
+        names.join(", ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_ensure() {
+        let dir = env::temp_dir().join(format!("dev_cert_test_{}", process::id()));
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (cert, key) = (path("cert.pem"), path("key.pem"));
+        ensure(&cert, &key, &[]).unwrap();
+        let generated = fs::read(&cert).unwrap();
+        ensure(&cert, &key, &[]).unwrap();
+        assert_eq!(fs::read(&cert).unwrap(), generated);
+
+        // a mistyped key path must not replace the certificate
+        assert!(ensure(&cert, &path("kye.pem"), &[]).is_err());
+        assert_eq!(fs::read(&cert).unwrap(), generated);
+        assert!(!Path::new(&path("kye.pem")).exists());
+        assert!(ensure(&path("cret.pem"), &key, &[]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
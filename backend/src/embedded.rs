@@ -0,0 +1,41 @@
+//! The frontend, compiled into the binary with the `embed-frontend` feature.
+
+pub struct EmbeddedFile {
+    /// relative to the frontend directory, e.g. `index.html`
+    pub path: &'static str,
+    pub contents: &'static [u8],
+    /// quoted, from a hash of the contents
+    pub etag: &'static str,
+}
+
+#[cfg(feature = "embed-frontend")]
+include!(concat!(env!("OUT_DIR"), "/frontend.rs"));
+
+#[cfg(not(feature = "embed-frontend"))]
+pub const BUILT: i64 = 0;
+
+#[cfg(not(feature = "embed-frontend"))]
+pub static FILES: &[EmbeddedFile] = &[];
+
+/// Whether this build carries the frontend at all.
+pub const AVAILABLE: bool = cfg!(feature = "embed-frontend");
+
+pub fn get(path: &str) -> Option<&'static EmbeddedFile> {
+    FILES
+        .binary_search_by(|file| file.path.cmp(path))
+        .ok()
+        .map(|i| &FILES[i])
+}
+
+#[cfg(all(test, feature = "embed-frontend"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let index = get("index.html").unwrap();
+        assert_eq!(index.contents, include_bytes!("../../frontend/index.html"));
+        assert!(index.etag.starts_with('"') && index.etag.ends_with('"'));
+        assert!(get("missing.html").is_none());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::static_files::SIDECARS;
+use crate::warn;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// bound on the bytes held, over all files and encodings
+    pub max_bytes: usize,
+    /// larger files are always read from disk
+    pub max_file_bytes: usize,
+}
+
+/// One encoding of a file, as sent.
+pub struct CachedFile {
+    pub contents: Vec<u8>,
+    pub etag: String,
+    /// seconds since the epoch
+    pub modified: i64,
+}
+
+struct Entry {
+    /// encodings with a precompressed sidecar file
+    sidecars: Vec<&'static str>,
+    variants: HashMap<Option<&'static str>, Rc<CachedFile>>,
+    bytes: usize,
+    last_used: u64,
+}
+
+struct Inner {
+    entries: HashMap<PathBuf, Entry>,
+    bytes: usize,
+    // bumped on every use, for least recently used eviction
+    clock: u64,
+    watches: HashMap<WatchDescriptor, PathBuf>,
+}
+
+/// A bounded LRU cache of static files, kept current through inotify.
+pub struct FileCache {
+    config: CacheConfig,
+    inotify: Inotify,
+    inner: RefCell<Inner>,
+}
+
+// everything that can change what a path holds
+fn watch_flags() -> AddWatchFlags {
+    AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_ATTRIB
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVED_FROM
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_DELETE_SELF
+        | AddWatchFlags::IN_MOVE_SELF
+}
+
+impl FileCache {
+    /// Watches every directory under `root`; inotify watches are not
+    /// recursive. Files reached through symlinks out of the root are not
+    /// watched, so do not cache with `symlinks_outside_root`.
+    pub fn new(root: &Path, config: &CacheConfig) -> nix::Result<FileCache> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let cache = FileCache {
+            config: config.clone(),
+            inotify,
+            inner: RefCell::new(Inner {
+                entries: HashMap::new(),
+                bytes: 0,
+                clock: 0,
+                watches: HashMap::new(),
+            }),
+        };
+        cache.watch_tree(root)?;
+        Ok(cache)
+    }
+
+    fn watch_tree(&self, dir: &Path) -> nix::Result<()> {
+        let wd = self.inotify.add_watch(dir, watch_flags())?;
+        self.inner
+            .borrow_mut()
+            .watches
+            .insert(wd, dir.to_path_buf());
+        // a directory that vanished meanwhile sends its own event
+        if let Ok(children) = fs::read_dir(dir) {
+            for child in children.flatten() {
+                if child.file_type().is_ok_and(|t| t.is_dir()) {
+                    self.watch_tree(&child.path())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // drops entries for whatever changed since the last call
+    fn invalidate(&self) {
+        let events = match self.inotify.read_events() {
+            Ok(events) => events,
+            Err(Errno::EAGAIN) => return,
+            Err(e) => {
+                warn!("could not read inotify events, emptying file cache: {}", e);
+                self.clear();
+                return;
+            }
+        };
+        for event in events {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                warn!("inotify queue overflowed, emptying file cache");
+                self.clear();
+                continue;
+            }
+            let dir = match self.inner.borrow().watches.get(&event.wd) {
+                Some(dir) => dir.clone(),
+                None => continue,
+            };
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                self.inner.borrow_mut().watches.remove(&event.wd);
+                continue;
+            }
+            let changed = match &event.name {
+                Some(name) => dir.join(name),
+                None => dir,
+            };
+            if event.mask.contains(AddWatchFlags::IN_ISDIR)
+                && event
+                    .mask
+                    .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+            {
+                if let Err(e) = self.watch_tree(&changed) {
+                    warn!("could not watch {}: {}", changed.display(), e);
+                }
+            }
+            self.forget(&changed);
+        }
+    }
+
+    fn clear(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.entries.clear();
+        inner.bytes = 0;
+    }
+
+    // entries for `changed`, anything below it, and files it is a sidecar of
+    fn forget(&self, changed: &Path) {
+        let mut inner = self.inner.borrow_mut();
+        let is_sidecar = |path: &Path| {
+            SIDECARS.iter().any(|(_, extension)| {
+                let mut sidecar = path.as_os_str().to_owned();
+                sidecar.push(extension);
+                sidecar == changed.as_os_str()
+            })
+        };
+        let stale: Vec<PathBuf> = inner
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(changed) || is_sidecar(path))
+            .cloned()
+            .collect();
+        for path in stale {
+            if let Some(entry) = inner.entries.remove(&path) {
+                inner.bytes -= entry.bytes;
+            }
+        }
+    }
+
+    /// The encodings `path` has sidecars for, if it is cached at all.
+    pub fn sidecars(&self, path: &Path) -> Option<Vec<&'static str>> {
+        self.invalidate();
+        let inner = self.inner.borrow();
+        inner.entries.get(path).map(|entry| entry.sidecars.clone())
+    }
+
+    pub fn get(&self, path: &Path, encoding: Option<&'static str>) -> Option<Rc<CachedFile>> {
+        self.invalidate();
+        let mut inner = self.inner.borrow_mut();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(path)?;
+        let file = entry.variants.get(&encoding)?.clone();
+        entry.last_used = clock;
+        Some(file)
+    }
+
+    /// Stores one encoding of `path`, evicting the least recently used
+    /// files to make room. Files over the size limit are passed through.
+    /// Whether a file of `size` bytes would be kept at all.
+    pub fn fits(&self, size: u64) -> bool {
+        size <= self.config.max_file_bytes as u64 && size <= self.config.max_bytes as u64
+    }
+
+    pub fn insert(
+        &self,
+        path: &Path,
+        sidecars: &[&'static str],
+        encoding: Option<&'static str>,
+        file: CachedFile,
+    ) -> Rc<CachedFile> {
+        self.invalidate();
+        let file = Rc::new(file);
+        let size = file.contents.len();
+        if !self.fits(size as u64) {
+            return file;
+        }
+        let mut inner = self.inner.borrow_mut();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.entry(path.to_path_buf()).or_insert(Entry {
+            sidecars: sidecars.to_vec(),
+            variants: HashMap::new(),
+            bytes: 0,
+            last_used: clock,
+        });
+        let replaced = entry.variants.insert(encoding, file.clone());
+        let freed = replaced.map_or(0, |old| old.contents.len());
+        entry.bytes = entry.bytes + size - freed;
+        entry.last_used = clock;
+        inner.bytes = inner.bytes + size - freed;
+        while inner.bytes > self.config.max_bytes {
+            let oldest = inner
+                .entries
+                .iter()
+                .filter(|(p, _)| p.as_path() != path)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(p, _)| p.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            if let Some(entry) = inner.entries.remove(&oldest) {
+                inner.bytes -= entry.bytes;
+            }
+        }
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn file(contents: &[u8]) -> CachedFile {
+        CachedFile {
+            contents: contents.to_vec(),
+            etag: "\"x\"".to_string(),
+            modified: 0,
+        }
+    }
+
+    #[test]
+    fn test_file_cache() {
+        let root = env::temp_dir().join(format!("file_cache_test_{}", process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let (a, b, c) = (root.join("a.js"), root.join("b.js"), root.join("sub/c.js"));
+        for path in [&a, &b, &c] {
+            fs::write(path, "0123456789").unwrap();
+        }
+        let config = CacheConfig {
+            max_bytes: 25,
+            max_file_bytes: 10,
+        };
+        let cache = FileCache::new(&root, &config).unwrap();
+
+        cache.insert(&a, &["gzip"], None, file(b"0123456789"));
+        cache.insert(&b, &[], None, file(b"0123456789"));
+        assert_eq!(cache.sidecars(&a), Some(vec!["gzip"]));
+        assert!(cache.get(&a, None).is_some());
+        assert!(cache.get(&a, Some("gzip")).is_none());
+        // too big to keep
+        cache.insert(&c, &[], None, file(b"0123456789abc"));
+        assert!(cache.get(&c, None).is_none());
+        // b is the least recently used
+        cache.insert(&c, &[], None, file(b"0123456789"));
+        assert!(cache.get(&b, None).is_none());
+        assert!(cache.get(&a, None).is_some());
+
+        // edits are seen, in subdirectories and for sidecars too
+        fs::write(&c, "changed").unwrap();
+        assert!(cache.get(&c, None).is_none());
+        // but files that merely start with its name are not its sidecars
+        fs::write(root.join("a.jsx"), "x").unwrap();
+        fs::write(root.join("a.js.map"), "x").unwrap();
+        assert!(cache.get(&a, None).is_some());
+        fs::write(root.join("a.js.gz"), "zz").unwrap();
+        assert!(cache.get(&a, None).is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
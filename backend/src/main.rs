@@ -1,25 +1,58 @@
 mod api;
 mod authenticator;
+mod autoindex;
 mod base64;
 mod config;
+mod dev_cert;
+mod embedded;
+mod file_cache;
 mod my_logger;
 mod note_db;
+mod note_watch;
+mod sites;
 mod sqlite_db;
+mod static_files;
 use crate::api::ApiHandler;
 use crate::config::*;
 use crate::my_logger::*;
+use crate::sites::{Site, Sites};
+use crate::static_files::StaticFiles;
+use http::deferred::{PendingResponse, Reply};
+use http::metrics::MetricsHandler;
+use http::rate_limit::{RateLimited, RateLimiter};
 use http::server::*;
 use http::types::*;
+use http::{ConnectionLimits, OverloadPolicy, ServerConfig};
 use std::env;
-use std::fs;
 
 struct MyHandler<'a> {
     config: &'a Config,
+    sites: Sites,
+}
+
+impl<'a> MyHandler<'a> {
+    fn is_metrics(&self, request: &HttpRequest) -> bool {
+        match &self.config.metrics {
+            Some(m) => {
+                m.address.is_none()
+                    && request.path == m.path
+                    && (m.public || request.client_addr.is_loopback())
+            }
+            None => false,
+        }
+    }
+
+    fn is_api(&self, request: &HttpRequest) -> bool {
+        request.path.starts_with("/api") && self.sites.select(request).api
+    }
 }
 
 impl<'a> HttpHandler for MyHandler<'a> {
     fn handle(&self, request: HttpRequest) -> HttpResponse {
-        if request.path.starts_with("/api") {
+        if self.is_metrics(&request) {
+            return MetricsHandler.handle(request);
+        }
+        if self.is_api(&request) {
             let handler = ApiHandler {};
             return handler.handle(request);
         }
@@ -29,29 +62,33 @@ impl<'a> HttpHandler for MyHandler<'a> {
             Method::Post => {}
         }
 
-        let path_bytes = request.path.as_bytes();
-        if path_bytes[path_bytes.len() - 1] == b'/' {
-            return http_respond_file(
-                &(self.config.frontend_dir.clone() + &request.path + "index.html"),
-            );
-        } else {
-            return http_respond_file(&(self.config.frontend_dir.clone() + &request.path));
+        self.sites.select(&request).files.serve(&request)
+    }
+
+    fn reply(&self, request: HttpRequest, pending: PendingResponse) -> Reply {
+        if self.is_api(&request) && !self.is_metrics(&request) {
+            return ApiHandler {}.reply(request, pending);
         }
+        pending.forget();
+        Reply::Now(self.handle(request))
     }
-}
 
-fn http_respond_file(fp: &str) -> HttpResponse {
-    // TODO: Check permissions
-    let result = fs::read(fp);
-    let contents = match result {
-        Ok(c) => c,
-        Err(e) => {
-            warn!("fs::read({}) - {}", fp, e);
-            return HttpResponse::new(StatusCode::NotFound, None);
+    fn finish(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        // API clients get bare status codes
+        if !self.is_metrics(request) && !self.is_api(request) {
+            self.sites.select(request).files.error_page(response);
         }
-    };
+    }
 
-    return HttpResponse::new(StatusCode::OK, Some(contents));
+    fn route(&self, request: &HttpRequest) -> &'static str {
+        if self.is_metrics(request) {
+            "metrics"
+        } else if self.is_api(request) {
+            api::route(request)
+        } else {
+            "static"
+        }
+    }
 }
 
 fn main() {
@@ -66,14 +103,84 @@ fn main() {
         Ok(c) => c,
         Err(e) => panic!("Config error: {}", e),
     };
-    my_logger::init();
+    if let Err(e) = my_logger::init(&cfg.log) {
+        println!("could not open log file: {}", e);
+        std::process::exit(1);
+    }
     note_db::init(&cfg.database);
     println!("{:#?}", cfg);
 
-    let http_handler = MyHandler { config: &cfg };
+    if let (true, Some(tls)) = (cfg.dev_cert, &cfg.http.tls) {
+        // virtual hosts with certificates of their own get one each
+        let certs = std::iter::once((&tls.cert, &tls.key, &cfg.dev_hostnames))
+            .chain(tls.sni.iter().map(|sni| (&sni.cert, &sni.key, &sni.names)));
+        for (cert, key, hostnames) in certs {
+            if let Err(e) = dev_cert::ensure(cert, key, hostnames) {
+                warn!("could not generate development certificate: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(address) = cfg.metrics.as_ref().and_then(|m| m.address.clone()) {
+        let listener = ServerConfig {
+            address,
+            tls: None,
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+            limits: ConnectionLimits {
+                max_connections: 16,
+                max_per_ip: 0,
+                policy: OverloadPolicy::StopAccepting,
+            },
+            access_log: None,
+            compression: None,
+        };
+        std::thread::spawn(
+            move || match HttpServer::new(&listener, Box::new(MetricsHandler)) {
+                Ok(server) => server.listen(),
+                Err(e) => warn!("metrics listener: {}", e),
+            },
+        );
+    }
+
+    let mut limiter = RateLimiter::new(cfg.rate_limits.clone());
+    limiter.set_key_fn(Box::new(|request| {
+        let (_, name) = authenticator::authenticate_request(request).ok()?;
+        Some(format!("user:{}", name))
+    }));
+    let compression = cfg.http.compression.as_ref();
+    let mut sites = Sites::new(Site {
+        files: StaticFiles::new(cfg.frontend_dir.as_deref(), &cfg.static_files, compression),
+        api: cfg.api,
+    });
+    for host in &cfg.hosts {
+        let files = StaticFiles::new(
+            host.frontend_dir.as_deref(),
+            &host.static_files,
+            compression,
+        );
+        sites.add(
+            &host.names,
+            Site {
+                files,
+                api: host.api,
+            },
+        );
+    }
+    let http_handler = RateLimited::new(
+        MyHandler {
+            config: &cfg,
+            sites,
+        },
+        limiter,
+    );
     let http_server = match HttpServer::new(&cfg.http, Box::new(http_handler)) {
         Ok(s) => s,
-        Err(e) => panic!("{}", e),
+        Err(e) => {
+            warn!("{}", e);
+            std::process::exit(1);
+        }
     };
 
     http_server.listen();
@@ -1,30 +1,322 @@
+use http::date::DateTime;
 use log::LevelFilter;
 pub use log::{info, warn};
-use log::{Level, Metadata, Record};
+use log::{Metadata, Record};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-struct SimpleLogger;
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    /// one JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub enum LogOutput {
+    Stdout,
+    Stderr,
+    File(FileOutput),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RotateInterval {
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileOutput {
+    pub path: String,
+    /// rotate once the file reaches this size, 0 for no limit
+    pub max_bytes: u64,
+    pub interval: Option<RotateInterval>,
+    /// rotated files kept as `path.1` (newest) to `path.keep`
+    pub keep: usize,
+}
+
+#[derive(Debug)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    /// (module path prefix, level), the longest matching prefix wins
+    pub modules: Vec<(String, LevelFilter)>,
+    pub format: LogFormat,
+    pub timestamps: bool,
+    pub output: LogOutput,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: LevelFilter::Info,
+            modules: Vec::new(),
+            format: LogFormat::Text,
+            timestamps: true,
+            output: LogOutput::Stdout,
+        }
+    }
+}
+
+impl RotateInterval {
+    fn seconds(&self) -> u64 {
+        match self {
+            RotateInterval::Hourly => 3600,
+            RotateInterval::Daily => 86400,
+        }
+    }
+}
+
+fn period(interval: RotateInterval, time: SystemTime) -> u64 {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    secs / interval.seconds()
+}
+
+struct RotatingFile {
+    config: FileOutput,
+    file: File,
+    size: u64,
+    // rotation period the current file belongs to
+    period: Option<u64>,
+}
+
+impl RotatingFile {
+    fn open(config: FileOutput) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let metadata = file.metadata()?;
+        let started = metadata.modified().unwrap_or(SystemTime::now());
+        let mut rotating = RotatingFile {
+            period: config.interval.map(|i| period(i, started)),
+            size: metadata.len(),
+            file,
+            config,
+        };
+        // a file left over from an earlier period is rotated right away
+        if rotating.size > 0 && rotating.due(0) {
+            rotating.rotate()?;
+        }
+        Ok(rotating)
+    }
+
+    fn due(&self, incoming: usize) -> bool {
+        let max = self.config.max_bytes;
+        if max != 0 && self.size > 0 && self.size + incoming as u64 > max {
+            return true;
+        }
+        match (self.config.interval, self.period) {
+            (Some(i), Some(p)) => period(i, SystemTime::now()) != p,
+            _ => false,
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.config.path;
+        let keep = self.config.keep;
+        if keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            let _ = fs::remove_file(format!("{}.{}", path, keep));
+            for n in (1..keep).rev() {
+                let _ = fs::rename(format!("{}.{}", path, n), format!("{}.{}", path, n + 1));
+            }
+            fs::rename(path, format!("{}.1", path))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.size = 0;
+        self.period = self.config.interval.map(|i| period(i, SystemTime::now()));
+        Ok(())
+    }
 
-impl log::Log for SimpleLogger {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.due(line.len()) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+enum Output {
+    Stdout,
+    Stderr,
+    File(RotatingFile),
+}
+
+struct Logger {
+    level: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+    format: LogFormat,
+    timestamps: bool,
+    output: Mutex<Output>,
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let time = self
+            .timestamps
+            .then(|| DateTime::from_system_time(SystemTime::now()).rfc3339());
+        let id = http::request_id::current();
+        let mut line = match self.format {
+            LogFormat::Json => {
+                let mut object = json!({
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                if let Some(time) = time {
+                    object["time"] = json!(time);
+                }
+                if let Some(id) = id {
+                    object["request_id"] = json!(id);
+                }
+                object.to_string()
+            }
+            LogFormat::Text => {
+                let mut line = String::new();
+                if let Some(time) = time {
+                    line += &time;
+                    line += " ";
+                }
+                line += &format!("{:<5} {}: ", record.level(), record.target());
+                if let Some(id) = id {
+                    line += &format!("[{}] ", id);
+                }
+                line += &record.args().to_string();
+                line
+            }
+        };
+        line.push('\n');
+        line
+    }
+}
+
+impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= self.level_for(metadata.target())
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            match record.level() {
-                Level::Info => println!("{}", record.args()),
-                _ => println!("{} - {}", record.level(), record.args()),
-            }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        // a panic while logging leaves the output usable
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let result = match &mut *output {
+            Output::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Output::Stderr => io::stderr().lock().write_all(line.as_bytes()),
+            Output::File(file) => file.write_line(&line),
+        };
+        if let Err(e) = result {
+            eprintln!("could not write log: {}", e);
         }
     }
 
     fn flush(&self) {}
 }
 
-static LOGGER: SimpleLogger = SimpleLogger;
-
-pub fn init() {
-    if let Err(e) = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Info)) {
-        println!("NO LOGGER: {}", e);
+pub fn init(config: &LogConfig) -> io::Result<()> {
+    let output = match &config.output {
+        LogOutput::Stdout => Output::Stdout,
+        LogOutput::Stderr => Output::Stderr,
+        LogOutput::File(file) => Output::File(RotatingFile::open(file.clone())?),
+    };
+    let max_level = config
+        .modules
+        .iter()
+        .map(|(_, level)| *level)
+        .fold(config.level, Ord::max);
+    let logger = Logger {
+        level: config.level,
+        modules: config.modules.clone(),
+        format: config.format,
+        timestamps: config.timestamps,
+        output: Mutex::new(output),
     };
+    // the logger lives for the rest of the program
+    if let Err(e) = log::set_logger(Box::leak(Box::new(logger))) {
+        println!("NO LOGGER: {}", e);
+    }
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_for() {
+        let logger = Logger {
+            level: LevelFilter::Info,
+            modules: vec![
+                ("http".to_string(), LevelFilter::Warn),
+                ("http::server".to_string(), LevelFilter::Debug),
+            ],
+            format: LogFormat::Text,
+            timestamps: false,
+            output: Mutex::new(Output::Stdout),
+        };
+        assert_eq!(logger.level_for("http::server"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("http::socket"), LevelFilter::Warn);
+        assert_eq!(logger.level_for("httpx"), LevelFilter::Info);
+        assert_eq!(logger.level_for("personal_site::api"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn test_rotate_bytes() {
+        let dir = std::env::temp_dir().join(format!("my_logger_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log").to_str().unwrap().to_string();
+        let mut file = RotatingFile::open(FileOutput {
+            path: path.clone(),
+            max_bytes: 10,
+            interval: None,
+            keep: 2,
+        })
+        .unwrap();
+        for n in 1..=4 {
+            file.write_line(&format!("line {}\n", n)).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+        assert_eq!(read("app.log").as_deref(), Some("line 4\n"));
+        assert_eq!(read("app.log.1").as_deref(), Some("line 3\n"));
+        // the oldest, line 1, was pruned
+        assert_eq!(read("app.log.2").as_deref(), Some("line 2\n"));
+        assert_eq!(read("app.log.3"), None);
+
+        // reopened, the size so far still counts
+        drop(file);
+        let mut file = RotatingFile::open(FileOutput {
+            path,
+            max_bytes: 10,
+            interval: None,
+            keep: 2,
+        })
+        .unwrap();
+        file.write_line("line 5\n").unwrap();
+        assert_eq!(read("app.log").as_deref(), Some("line 5\n"));
+        assert_eq!(read("app.log.1").as_deref(), Some("line 4\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,12 +1,19 @@
 //use crate::nodb::NoDB;
 use crate::my_logger::warn;
 use crate::sqlite_db::SqliteDB;
+use std::sync::{Mutex, MutexGuard};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub type NoteId = i64;
+#[allow(dead_code)]
 pub type UserId = i64;
 
-static mut DATABASE: SqliteDB = SqliteDB::new();
+static DATABASE: Mutex<SqliteDB> = Mutex::new(SqliteDB::new());
+
+fn database() -> MutexGuard<'static, SqliteDB> {
+    // a poisoned lock still holds a usable connection
+    DATABASE.lock().unwrap_or_else(|e| e.into_inner())
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Note {
@@ -29,6 +36,7 @@ pub fn now() -> i64 {
 }
 
 impl Note {
+    #[allow(dead_code)]
     pub fn new_str(s: &str) -> Note {
         Note {
             text: s.to_string(),
@@ -40,7 +48,7 @@ impl Note {
         Note {
             text: s,
             date: now(),
-            author: author,
+            author,
         }
     }
 }
@@ -48,8 +56,10 @@ impl Note {
 pub trait NoteDB {
     fn save(&mut self, n: &Note) -> NoteId;
     fn get(&self, id: &NoteId) -> Option<NoteEntry>;
+    #[allow(dead_code)]
     fn delete(&mut self, id: &NoteId);
     fn delete_if_user(&mut self, id: &NoteId, passkey: i64);
+    #[allow(dead_code)]
     fn public(&self) -> Vec<NoteEntry>;
     fn by_passkey(&self, passkey: i64) -> Vec<NoteEntry>;
 
@@ -58,39 +68,41 @@ pub trait NoteDB {
 }
 
 pub fn save(n: &Note) -> NoteId {
-    unsafe { DATABASE.save(n) }
+    database().save(n)
 }
 
 pub fn get(id: &NoteId) -> Option<NoteEntry> {
-    unsafe { DATABASE.get(id) }
+    database().get(id)
 }
 
+#[allow(dead_code)]
 pub fn delete(id: &NoteId) {
-    unsafe { DATABASE.delete(id) }
+    database().delete(id)
 }
 
 pub fn delete_if_user(id: &NoteId, passkey: i64) {
-    unsafe { DATABASE.delete_if_user(id, passkey) }
+    database().delete_if_user(id, passkey)
 }
 
+#[allow(dead_code)]
 pub fn public() -> Vec<NoteEntry> {
-    unsafe { DATABASE.public() }
+    database().public()
 }
 
 pub fn by_passkey(passkey: i64) -> Vec<NoteEntry> {
-    unsafe { DATABASE.by_passkey(passkey) }
+    database().by_passkey(passkey)
 }
 
 pub fn init(path: &str) {
-    if let Err(_) = unsafe { DATABASE.init(path) } {
+    if database().init(path).is_err() {
         warn!("Could not initialise database {}", path);
     }
 }
 
 pub fn create_user(name: &str, time: i64, passkey: i64) -> Option<()> {
-    unsafe { DATABASE.create_user(name, time, passkey) }
+    database().create_user(name, time, passkey)
 }
 
 pub fn get_user_by_passkey(passkey: i64) -> Option<String> {
-    unsafe { DATABASE.get_user_by_passkey(passkey) }
+    database().get_user_by_passkey(passkey)
 }
@@ -0,0 +1,141 @@
+use crate::api;
+use crate::info;
+use http::deferred::PendingResponse;
+use http::request_id;
+use http::types::HttpResponse;
+use serde_json::json;
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a client waits for a change before being told there was none.
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(25);
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Waiter {
+    passkey: i64,
+    pending: PendingResponse,
+    deadline: Instant,
+    /// of the waiting request, for what is logged when it is answered
+    request_id: Option<String>,
+}
+
+impl Waiter {
+    fn complete(self, response: HttpResponse) {
+        let _id_scope = self.request_id.as_deref().map(request_id::enter);
+        info!("answering a wait for changes");
+        self.pending.complete(response);
+    }
+}
+
+static WAITERS: Mutex<Vec<Waiter>> = Mutex::new(Vec::new());
+static EXPIRY: Once = Once::new();
+
+fn waiters() -> MutexGuard<'static, Vec<Waiter>> {
+    WAITERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn changed(changed: bool) -> HttpResponse {
+    let body = json!({ "changed": changed }).to_string();
+    api::json_response(Some(body.into_bytes()))
+}
+
+/// Answers `pending` once the notes of `passkey` change, or after `timeout`.
+pub fn wait(passkey: i64, pending: PendingResponse, timeout: Duration) {
+    EXPIRY.call_once(|| {
+        thread::spawn(|| loop {
+            thread::sleep(CHECK_INTERVAL);
+            expire(Instant::now());
+        });
+    });
+    waiters().push(Waiter {
+        passkey,
+        pending,
+        deadline: Instant::now() + timeout,
+        request_id: request_id::current(),
+    });
+}
+
+/// Wakes everyone waiting on the notes of `passkey`.
+pub fn notify(passkey: i64) {
+    let woken: Vec<Waiter> = waiters().extract_if(.., |w| w.passkey == passkey).collect();
+    for waiter in woken {
+        waiter.complete(changed(true));
+    }
+}
+
+fn expire(now: Instant) {
+    let expired: Vec<Waiter> = waiters()
+        .extract_if(.., |w| w.deadline <= now || w.pending.is_cancelled())
+        .collect();
+    for waiter in expired {
+        if waiter.pending.is_cancelled() {
+            // the client hung up, nobody to tell
+            waiter.pending.forget();
+        } else {
+            waiter.complete(changed(false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::deferred::Completions;
+
+    fn taken(completions: &Completions) -> Vec<(u64, String)> {
+        completions
+            .take()
+            .into_iter()
+            .map(|(key, response)| (key, String::from_utf8(response.body.unwrap()).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_notify_and_expire() {
+        // passkeys no real account has, and timeouts the expiry thread won't reach
+        let mut completions = Completions::new().unwrap();
+        let (a, b, c) = (
+            completions.pending(),
+            completions.pending(),
+            completions.pending(),
+        );
+        let (key_a, key_b, key_c) = (a.key(), b.key(), c.key());
+        let hour = Duration::from_secs(3600);
+        {
+            let _id_scope = request_id::enter("waiting-a");
+            wait(-1, a, hour);
+        }
+        let id_of = |key| {
+            let waiters = waiters();
+            let waiter = waiters.iter().find(|w| w.pending.key() == key);
+            waiter.and_then(|w| w.request_id.clone())
+        };
+        assert_eq!(id_of(key_a).as_deref(), Some("waiting-a"));
+        wait(-2, b, hour);
+        wait(-1, c, hour * 2);
+
+        notify(-1);
+        assert_eq!(
+            taken(&completions),
+            [
+                (key_a, r#"{"changed":true}"#.to_string()),
+                (key_c, r#"{"changed":true}"#.to_string())
+            ]
+        );
+        // nobody waits on -1 anymore
+        notify(-1);
+        assert!(taken(&completions).is_empty());
+
+        expire(Instant::now());
+        assert!(taken(&completions).is_empty());
+        expire(Instant::now() + hour * 2);
+        assert_eq!(
+            taken(&completions),
+            [(key_b, r#"{"changed":false}"#.to_string())]
+        );
+        notify(-2);
+        assert!(taken(&completions).is_empty());
+    }
+}
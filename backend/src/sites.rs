@@ -0,0 +1,132 @@
+use crate::static_files::StaticFiles;
+use http::types::HttpRequest;
+use std::collections::HashMap;
+
+/// What one virtual host serves.
+pub struct Site {
+    pub files: StaticFiles,
+    /// serve `/api`, rather than treating it as a path under the root
+    pub api: bool,
+}
+
+/// The sites behind one listener, told apart by `Host`, or by SNI for
+/// requests without it.
+pub struct Sites {
+    default: Site,
+    hosts: Vec<Site>,
+    /// lowercase name to index in `hosts`
+    names: HashMap<String, usize>,
+}
+
+/// The name in a `Host` value, without the port or a trailing dot, and
+/// lowercase.
+pub fn host_name(host: &str) -> String {
+    let name = match host.strip_prefix('[') {
+        // an IPv6 literal keeps its brackets
+        Some(rest) => match rest.split_once(']') {
+            Some((address, _)) => &host[..address.len() + 2],
+            None => host,
+        },
+        None => match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => host,
+        },
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl Sites {
+    pub fn new(default: Site) -> Sites {
+        Sites {
+            default,
+            hosts: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// `names` are expected as `host_name` gives them.
+    pub fn add(&mut self, names: &[String], site: Site) {
+        for name in names {
+            self.names.insert(name.clone(), self.hosts.len());
+        }
+        self.hosts.push(site);
+    }
+
+    /// The site `request` is for, or the default one for unknown names.
+    pub fn select(&self, request: &HttpRequest) -> &Site {
+        let name = match &request.host {
+            Some(host) => Some(host_name(host)),
+            None => request
+                .connection
+                .tls
+                .as_ref()
+                .and_then(|tls| tls.sni.as_deref())
+                .map(host_name),
+        };
+        match name.and_then(|name| self.names.get(&name)) {
+            Some(&i) => &self.hosts[i],
+            None => &self.default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::static_files::StaticConfig;
+    use http::types::{Method, TlsInfo};
+
+    fn site() -> Site {
+        Site {
+            files: StaticFiles::new(None, &StaticConfig::default(), None),
+            api: false,
+        }
+    }
+
+    fn request(host: Option<&str>, sni: Option<&str>) -> HttpRequest {
+        let request = HttpRequest::new(Method::Get, "/").with_tls(TlsInfo {
+            sni: sni.map(str::to_string),
+            alpn: None,
+            client_cert: None,
+        });
+        match host {
+            Some(host) => request.with_host(host),
+            None => request,
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let mut sites = Sites::new(site());
+        sites.add(&["example.com".to_string()], site());
+        sites.add(&["blog.example.com".to_string()], site());
+        let selected = |host, sni| {
+            let site = sites.select(&request(host, sni));
+            if std::ptr::eq(site, &sites.default) {
+                None
+            } else {
+                sites.hosts.iter().position(|h| std::ptr::eq(site, h))
+            }
+        };
+
+        assert_eq!(selected(Some("Example.com:443"), None), Some(0));
+        // Host wins over SNI
+        assert_eq!(
+            selected(Some("blog.example.com"), Some("example.com")),
+            Some(1)
+        );
+        assert_eq!(selected(None, Some("blog.example.com")), Some(1));
+        assert_eq!(selected(Some("unknown.test"), Some("example.com")), None);
+        assert_eq!(selected(None, None), None);
+    }
+
+    #[test]
+    fn test_host_name() {
+        assert_eq!(host_name("example.com"), "example.com");
+        assert_eq!(host_name("Example.COM:8443"), "example.com");
+        assert_eq!(host_name("example.com."), "example.com");
+        assert_eq!(host_name("[::1]:7878"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+        assert_eq!(host_name("127.0.0.1:80"), "127.0.0.1");
+    }
+}
@@ -26,10 +26,7 @@ impl SqliteDB {
 }
 
 fn statement_to_entry(statement: &Statement<'_>) -> Option<NoteEntry> {
-    match statement_to_entry_err(statement) {
-        Ok(e) => Some(e),
-        Err(_) => None,
-    }
+    statement_to_entry_err(statement).ok()
 }
 
 fn statement_to_entry_err(statement: &Statement<'_>) -> Result<NoteEntry, sqlite::Error> {
@@ -38,7 +35,7 @@ fn statement_to_entry_err(statement: &Statement<'_>) -> Result<NoteEntry, sqlite
     let time: i64 = statement.read::<i64, _>("time")?;
     let contents = from_sql_string(&statement.read::<String, _>("contents")?);
     let entry = NoteEntry {
-        id: id,
+        id,
         note: Note {
             text: contents,
             date: time,
@@ -49,11 +46,11 @@ fn statement_to_entry_err(statement: &Statement<'_>) -> Result<NoteEntry, sqlite
 }
 
 fn into_sql_string(string: &str) -> String {
-    return "'".to_string() + &base64::encode(string.as_bytes()) + "'";
+    "'".to_string() + &base64::encode(string.as_bytes()) + "'"
 }
 
 fn from_sql_string(string: &str) -> String {
-    return String::from_utf8(base64::decode(string).unwrap()).unwrap();
+    String::from_utf8(base64::decode(string).unwrap()).unwrap()
 }
 
 impl NoteDB for SqliteDB {
@@ -81,7 +78,7 @@ impl NoteDB for SqliteDB {
             let id: i64 = statement.read::<i64, _>("id").unwrap();
             return id;
         }
-        return 0;
+        0
     }
 
     fn get(&self, id: &NoteId) -> Option<NoteEntry> {
@@ -184,10 +181,10 @@ impl NoteDB for SqliteDB {
         );
 
         match connection.execute(query) {
-            Ok(()) => return Some(()),
+            Ok(()) => Some(()),
             Err(e) => {
                 warn!("Error creating user: {}", e);
-                return None;
+                None
             }
         }
     }
@@ -0,0 +1,1053 @@
+use crate::autoindex::{self, Entry};
+use crate::embedded;
+use crate::file_cache::{CacheConfig, CachedFile, FileCache};
+use crate::warn;
+use http::compression;
+use http::date::DateTime;
+use http::range::{self, RangeRequest};
+use http::types::{HttpRequest, HttpResponse, Method, StatusCode};
+use http::CompressionConfig;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Precompressed sidecar files, (encoding, extension), preferred first.
+pub const SIDECARS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// Built-in extension to MIME type table, extended or overridden by
+/// `static.mime_types` in the config.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct StaticConfig {
+    /// (extension without the dot, MIME type)
+    pub mime_types: Vec<(String, String)>,
+    /// serve files and directories whose name starts with `.`
+    pub dotfiles: bool,
+    /// follow symlinks that point outside the root
+    pub symlinks_outside_root: bool,
+    /// (path pattern, `Cache-Control` value), the first match wins
+    pub cache_control: Vec<(String, String)>,
+    /// serve `.br` and `.gz` files next to the requested one to clients
+    /// that accept them
+    pub precompressed: bool,
+    /// keep files in memory, `None` to read them on every request
+    pub cache: Option<CacheConfig>,
+    /// serve the frontend compiled into the binary
+    pub embedded: bool,
+    /// (status code, page under the root) sent as the body of otherwise
+    /// empty error responses
+    pub error_pages: Vec<(u16, String)>,
+    /// page under the root, e.g. `index.html`, for GETs of paths that
+    /// name no file and look like client-side routes
+    pub fallback: Option<String>,
+    /// patterns for directories, e.g. `/downloads/*`, listed when they
+    /// have no `index.html`
+    pub autoindex: Vec<String>,
+}
+
+/// Why a request path does not name a file under the root.
+#[derive(Debug, PartialEq)]
+pub enum PathError {
+    BadEncoding,
+    NulByte,
+    /// `..` would climb above the root
+    Escapes,
+    Hidden,
+}
+impl Error for PathError {}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use PathError::*;
+        match self {
+            BadEncoding => write!(f, "invalid percent-encoding"),
+            NulByte => write!(f, "NUL byte in path"),
+            Escapes => write!(f, "path leaves the root"),
+            Hidden => write!(f, "path names a dotfile"),
+        }
+    }
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn percent_decode(s: &str) -> Result<String, PathError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+        match rest {
+            [hi, lo, tail @ ..] => {
+                let hi = hex_digit(*hi).ok_or(PathError::BadEncoding)?;
+                let lo = hex_digit(*lo).ok_or(PathError::BadEncoding)?;
+                bytes.push(hi << 4 | lo);
+                rest = tail;
+            }
+            _ => return Err(PathError::BadEncoding),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| PathError::BadEncoding)
+}
+
+/// The segments of a request path below the root, decoded and with `.`
+/// and `..` resolved. Decoding comes first, so `%2e%2e` is `..` too.
+pub fn normalize(path: &str, dotfiles: bool) -> Result<Vec<String>, PathError> {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let decoded = percent_decode(path)?;
+    if decoded.contains('\0') {
+        return Err(PathError::NulByte);
+    }
+    let mut segments: Vec<String> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(PathError::Escapes);
+                }
+            }
+            // ACME challenges and the like live there
+            ".well-known" => segments.push(segment.to_string()),
+            s if s.starts_with('.') && !dotfiles => return Err(PathError::Hidden),
+            s => segments.push(s.to_string()),
+        }
+    }
+    Ok(segments)
+}
+
+// types whose body is text, so browsers need to know the charset
+fn is_text(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || matches!(
+            mime_type,
+            "application/json"
+                | "application/manifest+json"
+                | "application/xml"
+                | "application/javascript"
+                | "image/svg+xml"
+        )
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of
+/// characters, `/` included.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was, and how much of the text it has taken
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+// validators for a file version: (ETag, seconds since the epoch)
+fn validators(metadata: &fs::Metadata) -> (String, i64) {
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let seconds = DateTime::from_system_time(modified).unix_time();
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    // size and mtime change together with the content in practice
+    let etag = format!("\"{:x}-{:x}.{:x}\"", metadata.len(), seconds, nanos);
+    (etag, seconds)
+}
+
+/// Whether the client's copy, as described by its conditional request
+/// fields, is still current.
+fn not_modified(request: &HttpRequest, etag: &str, modified: i64) -> bool {
+    if request.method != Method::Get {
+        return false;
+    }
+    // If-None-Match takes precedence, and compares weakly
+    if let Some(tags) = request.field("If-None-Match") {
+        return tags.split(',').map(str::trim).any(|tag| {
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag == "*" || tag == etag || compression::uncompressed_etag(tag) == etag
+        });
+    }
+    match request
+        .field("If-Modified-Since")
+        .and_then(DateTime::parse_http_date)
+    {
+        Some(since) => modified <= since.unix_time(),
+        None => false,
+    }
+}
+
+/// Whether the last segment of a request path has an extension, as
+/// asset paths do and client-side routes like `/notes/42` do not.
+fn looks_like_file(path: &str) -> bool {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let name = path.rsplit('/').next().unwrap_or("");
+    name.contains('.')
+}
+
+/// Whether a `Range` may be applied: only to the version named by
+/// `If-Range`, if there is one. Weak ETags never match.
+fn if_range(request: &HttpRequest, etag: &str, last_modified: &str) -> bool {
+    match request.field("If-Range") {
+        None => true,
+        Some(value) if value.starts_with('"') => value == etag,
+        Some(value) => value == last_modified,
+    }
+}
+
+// what to send for a request, before reading it if it is not cached
+struct Selected {
+    path: PathBuf,
+    etag: String,
+    modified: i64,
+    encoding: Option<&'static str>,
+    /// other encodings exist
+    vary: bool,
+    len: u64,
+    contents: Contents,
+}
+
+// where the bytes of a `Selected` file are
+enum Contents {
+    /// to be read when sent
+    Disk,
+    Cached(Rc<CachedFile>),
+    Embedded(&'static [u8]),
+}
+
+impl Contents {
+    // the bytes, if they are in memory already
+    fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Contents::Disk => None,
+            Contents::Cached(file) => Some(&file.contents),
+            Contents::Embedded(contents) => Some(contents),
+        }
+    }
+}
+
+/// Serves files under a directory, and nothing outside it.
+pub struct StaticFiles {
+    root: Option<PathBuf>,
+    embedded: bool,
+    error_pages: Vec<(u16, String)>,
+    fallback: Option<String>,
+    autoindex: Vec<String>,
+    mime_types: HashMap<String, String>,
+    dotfiles: bool,
+    symlinks_outside_root: bool,
+    cache_control: Vec<(String, String)>,
+    precompressed: bool,
+    cache: Option<FileCache>,
+    /// how the server compresses, to do it ahead for cached files
+    compression: Option<CompressionConfig>,
+}
+
+impl StaticFiles {
+    /// Serves from `root`, and from the embedded frontend for whatever is
+    /// not there if `config.embedded`.
+    pub fn new(
+        root: Option<&str>,
+        config: &StaticConfig,
+        compression: Option<&CompressionConfig>,
+    ) -> StaticFiles {
+        let mut mime_types: HashMap<String, String> = MIME_TYPES
+            .iter()
+            .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+            .collect();
+        for (ext, mime) in &config.mime_types {
+            mime_types.insert(ext.to_ascii_lowercase(), mime.clone());
+        }
+        // symlinks are checked against where the root really is
+        let root = root.map(|root| {
+            fs::canonicalize(root).unwrap_or_else(|e| {
+                warn!("static root {}: {}", root, e);
+                PathBuf::from(root)
+            })
+        });
+        let cache = match (&config.cache, &root) {
+            (Some(cache), Some(root)) => match FileCache::new(root, cache) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    warn!(
+                        "file cache disabled, could not watch {}: {}",
+                        root.display(),
+                        e
+                    );
+                    None
+                }
+            },
+            _ => None,
+        };
+        StaticFiles {
+            root,
+            embedded: config.embedded,
+            error_pages: config.error_pages.clone(),
+            fallback: config.fallback.clone(),
+            autoindex: config.autoindex.clone(),
+            mime_types,
+            dotfiles: config.dotfiles,
+            symlinks_outside_root: config.symlinks_outside_root,
+            cache_control: config.cache_control.clone(),
+            precompressed: config.precompressed,
+            cache,
+            compression: compression.cloned(),
+        }
+    }
+
+    /// Precompressed copies of `fp` next to it, e.g. `index.js.gz`, that are
+    /// at least as new as the file itself: (encoding, path, metadata).
+    fn sidecars(
+        &self,
+        fp: &Path,
+        metadata: &fs::Metadata,
+    ) -> Vec<(&'static str, PathBuf, fs::Metadata)> {
+        if !self.precompressed {
+            return Vec::new();
+        }
+        let modified = metadata.modified().ok();
+        let mut sidecars = Vec::new();
+        for (encoding, extension) in SIDECARS {
+            let mut path = fp.as_os_str().to_owned();
+            path.push(extension);
+            let path = PathBuf::from(path);
+            // held to the same rule as the file itself
+            if self
+                .root
+                .as_ref()
+                .is_some_and(|root| self.escapes(root, &path))
+            {
+                warn!("refusing sidecar {} outside the root", path.display());
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(m) if m.is_file() && m.modified().ok() >= modified => {
+                    sidecars.push((encoding, path, m))
+                }
+                _ => {}
+            }
+        }
+        sidecars
+    }
+
+    fn cache_control(&self, path: &str) -> Option<&str> {
+        let path = path.split(['?', '#']).next().unwrap_or("");
+        self.cache_control
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, path))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The `Content-Type` for a file, with a charset for text.
+    pub fn content_type(&self, path: &Path) -> String {
+        let mime_type = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.mime_types.get(&ext.to_ascii_lowercase()))
+            .map_or(DEFAULT_MIME_TYPE, |mime| mime.as_str());
+        // an override may already say which charset
+        if is_text(mime_type) && !mime_type.contains(';') {
+            format!("{}; charset=utf-8", mime_type)
+        } else {
+            mime_type.to_string()
+        }
+    }
+
+    /// The file a request path names, if it may be served: its path
+    /// relative to the root, and on disk.
+    fn resolve(&self, path: &str) -> Result<(String, Option<PathBuf>), PathError> {
+        let mut segments = normalize(path, self.dotfiles)?;
+        if path.split(['?', '#']).next().unwrap_or("").ends_with('/') {
+            segments.push("index.html".to_string());
+        }
+        let relative = segments.join("/");
+        let Some(root) = &self.root else {
+            return Ok((relative, None));
+        };
+        let mut file = root.clone();
+        file.extend(&segments);
+        if self.escapes(root, &file) {
+            return Err(PathError::Escapes);
+        }
+        Ok((relative, Some(file)))
+    }
+
+    // whether `file` is a symlink, or under one, that leaves the root
+    fn escapes(&self, root: &Path, file: &Path) -> bool {
+        // a file that does not exist is a 404 later either way
+        !self.symlinks_outside_root
+            && fs::canonicalize(file).is_ok_and(|real| !real.starts_with(root))
+    }
+
+    // a listing of the directory `path` names, if autoindex is on for it
+    fn autoindex(&self, request: &HttpRequest, path: &str) -> Option<HttpResponse> {
+        let path = path.split(['?', '#']).next().unwrap_or("");
+        if !path.ends_with('/') {
+            return None;
+        }
+        // patterns match the decoded path, so encoding cannot dodge them
+        let segments = normalize(path, self.dotfiles).ok()?;
+        let mut dir_path = "/".to_string();
+        for segment in &segments {
+            dir_path += segment;
+            dir_path += "/";
+        }
+        if !self.autoindex.iter().any(|p| glob_match(p, &dir_path)) {
+            return None;
+        }
+        let root = self.root.as_ref()?;
+        let mut dir = root.clone();
+        dir.extend(&segments);
+        if self.escapes(root, &dir) {
+            warn!("refusing to list {}: {}", dir_path, PathError::Escapes);
+            return None;
+        }
+        let read = match fs::read_dir(&dir) {
+            Ok(read) => read,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("fs::read_dir({}) - {}", dir.display(), e);
+                }
+                return None;
+            }
+        };
+        let mut entries = Vec::new();
+        for dir_entry in read.flatten() {
+            let Ok(name) = dir_entry.file_name().into_string() else {
+                continue;
+            };
+            let (hidden, fp) = (name.starts_with('.'), dir_entry.path());
+            if hidden && !(self.dotfiles || name == ".well-known") || self.escapes(root, &fp) {
+                continue;
+            }
+            // follows symlinks, and skips dangling ones
+            let Ok(metadata) = fs::metadata(&fp) else {
+                continue;
+            };
+            if !metadata.is_file() && !metadata.is_dir() {
+                continue;
+            }
+            entries.push(Entry {
+                name,
+                dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            });
+        }
+        autoindex::sort(&mut entries);
+        let json = request
+            .field("Accept")
+            .is_some_and(|accept| accept.contains("application/json"));
+        let (body, content_type) = if json {
+            (autoindex::json(&entries), "application/json")
+        } else {
+            (
+                autoindex::html(&dir_path, &entries),
+                "text/html; charset=utf-8",
+            )
+        };
+        let mut response = HttpResponse::new(StatusCode::OK, Some(body.into_bytes()));
+        response
+            .fields
+            .push(("Content-Type".to_string(), content_type.to_string()));
+        response
+            .fields
+            .push(("Vary".to_string(), "Accept".to_string()));
+        if let Some(value) = self.cache_control(path) {
+            response
+                .fields
+                .push(("Cache-Control".to_string(), value.to_string()));
+        }
+        response
+            .fields
+            .push(("X-Content-Type-Options".to_string(), "nosniff".to_string()));
+        Some(response)
+    }
+
+    fn select_embedded(&self, relative: &str) -> Option<Selected> {
+        let file = embedded::get(relative)?;
+        Some(Selected {
+            path: PathBuf::from(relative),
+            etag: file.etag.to_string(),
+            modified: embedded::BUILT,
+            encoding: None,
+            vary: false,
+            len: file.contents.len() as u64,
+            contents: Contents::Embedded(file.contents),
+        })
+    }
+
+    // the cached encoding of `fp` the client would get, if there is one
+    fn select_cached(
+        &self,
+        cache: &FileCache,
+        request: &HttpRequest,
+        fp: &Path,
+    ) -> Option<Selected> {
+        let available = cache.sidecars(fp)?;
+        let encoding = request
+            .field("Accept-Encoding")
+            .and_then(|accept| compression::negotiate(accept, &available));
+        let file = cache.get(fp, encoding)?;
+        Some(Selected {
+            path: fp.to_path_buf(),
+            etag: file.etag.clone(),
+            modified: file.modified,
+            encoding,
+            vary: !available.is_empty(),
+            len: file.contents.len() as u64,
+            contents: Contents::Cached(file),
+        })
+    }
+
+    /// Picks the file to send for `fp`, itself or a precompressed sidecar.
+    fn select(&self, request: &HttpRequest, fp: &Path) -> Result<Selected, HttpResponse> {
+        if let Some(selected) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| self.select_cached(cache, request, fp))
+        {
+            return Ok(selected);
+        }
+        let metadata = match fs::metadata(fp) {
+            Ok(m) if m.is_file() => m,
+            Ok(_) => return Err(HttpResponse::new(StatusCode::NotFound, None)),
+            Err(e) => {
+                warn!("fs::metadata({}) - {}", fp.display(), e);
+                return Err(HttpResponse::new(StatusCode::NotFound, None));
+            }
+        };
+        let sidecars = self.sidecars(fp, &metadata);
+        let available: Vec<&'static str> =
+            sidecars.iter().map(|(encoding, _, _)| *encoding).collect();
+        let chosen = request
+            .field("Accept-Encoding")
+            .and_then(|accept| compression::negotiate(accept, &available));
+        let (path, metadata, encoding) = match sidecars.into_iter().find(|s| Some(s.0) == chosen) {
+            Some((encoding, path, metadata)) => (path, metadata, Some(encoding)),
+            None => (fp.to_path_buf(), metadata, None),
+        };
+        let (etag, modified) = validators(&metadata);
+        let mut selected = Selected {
+            path,
+            etag,
+            modified,
+            encoding,
+            vary: !available.is_empty(),
+            len: metadata.len(),
+            contents: Contents::Disk,
+        };
+        // files too big to keep are read when sent, as without a cache
+        if let Some(cache) = self.cache.as_ref().filter(|c| c.fits(selected.len)) {
+            let contents = match fs::read(&selected.path) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("fs::read({}) - {}", selected.path.display(), e);
+                    return Err(HttpResponse::new(StatusCode::NotFound, None));
+                }
+            };
+            let file = CachedFile {
+                contents,
+                etag: selected.etag.clone(),
+                modified,
+            };
+            selected.contents = Contents::Cached(cache.insert(fp, &available, encoding, file));
+        }
+        Ok(selected)
+    }
+
+    // compresses a cached file once rather than on every response
+    fn compress_cached(
+        &self,
+        request: &HttpRequest,
+        fp: &Path,
+        content_type: &str,
+        selected: Selected,
+    ) -> Selected {
+        let (Some(cache), Some(config), Contents::Cached(file), None) = (
+            &self.cache,
+            &self.compression,
+            &selected.contents,
+            selected.encoding,
+        ) else {
+            return selected;
+        };
+        if !compression::worthwhile(content_type, file.contents.len(), config) {
+            return selected;
+        }
+        let Some(encoding) = request
+            .field("Accept-Encoding")
+            .and_then(|accept| compression::negotiate(accept, &compression::ENCODINGS))
+        else {
+            return selected;
+        };
+        let compressed = match cache.get(fp, Some(encoding)) {
+            Some(compressed) => compressed,
+            None => match compression::encode(encoding, &file.contents, config.level) {
+                Ok(contents) => {
+                    let compressed = CachedFile {
+                        contents,
+                        etag: compression::compressed_etag(&file.etag, encoding),
+                        modified: file.modified,
+                    };
+                    cache.insert(fp, &[], Some(encoding), compressed)
+                }
+                Err(e) => {
+                    warn!("could not compress {}: {}", fp.display(), e);
+                    return selected;
+                }
+            },
+        };
+        Selected {
+            etag: compressed.etag.clone(),
+            encoding: Some(encoding),
+            vary: true,
+            len: compressed.contents.len() as u64,
+            contents: Contents::Cached(compressed),
+            ..selected
+        }
+    }
+
+    /// Answers with the file `request` names, a listing of a directory
+    /// without an index, or the fallback page for paths that look like
+    /// client-side routes.
+    pub fn serve(&self, request: &HttpRequest) -> HttpResponse {
+        let mut response = self.serve_path(request, &request.path);
+        if response.status_code == StatusCode::NotFound {
+            if let Some(listing) = self.autoindex(request, &request.path) {
+                response = listing;
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            if response.status_code == StatusCode::NotFound
+                && request.method == Method::Get
+                && !looks_like_file(&request.path)
+            {
+                response = self.serve_path(request, &format!("/{}", fallback));
+            }
+        }
+        response
+    }
+
+    /// Gives an empty-bodied error response its configured page, whatever
+    /// made it.
+    pub fn error_page(&self, response: &mut HttpResponse) {
+        if response.body.is_some() {
+            return;
+        }
+        let code = response.status_code.code();
+        let Some((_, page)) = self.error_pages.iter().find(|(c, _)| *c == code) else {
+            return;
+        };
+        let contents = match self.resolve(&format!("/{}", page)) {
+            Ok((relative, fp)) => match fp.filter(|fp| !self.embedded || fp.is_file()) {
+                Some(fp) => fs::read(&fp).ok(),
+                None => embedded::get(&relative).map(|file| file.contents.to_vec()),
+            },
+            Err(_) => None,
+        };
+        let Some(contents) = contents else {
+            warn!("error page {} for {} is missing", page, code);
+            return;
+        };
+        response.fields.push((
+            "Content-Type".to_string(),
+            self.content_type(Path::new(page)),
+        ));
+        response.body = Some(contents);
+    }
+
+    fn serve_path(&self, request: &HttpRequest, path: &str) -> HttpResponse {
+        let (relative, fp) = match self.resolve(path) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                warn!("refusing {}: {}", path, e);
+                return match e {
+                    PathError::Hidden => HttpResponse::new(StatusCode::NotFound, None),
+                    _ => HttpResponse::new(StatusCode::BadRequest, None),
+                };
+            }
+        };
+        let content_type = self.content_type(Path::new(&relative));
+        // files on disk override embedded ones
+        let selected = match fp.filter(|fp| !self.embedded || fp.is_file()) {
+            Some(fp) => match self.select(request, &fp) {
+                Ok(selected) => self.compress_cached(request, &fp, &content_type, selected),
+                Err(response) => return response,
+            },
+            None => match self.select_embedded(&relative) {
+                Some(selected) => selected,
+                None => return HttpResponse::new(StatusCode::NotFound, None),
+            },
+        };
+        let (etag, modified) = (selected.etag.clone(), selected.modified);
+        let last_modified =
+            DateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(modified as u64))
+                .http_date();
+        // sent with 304s as well, so caches can update what they hold
+        let mut fields = vec![
+            ("ETag".to_string(), etag.clone()),
+            ("Last-Modified".to_string(), last_modified.clone()),
+        ];
+        if let Some(value) = self.cache_control(path) {
+            fields.push(("Cache-Control".to_string(), value.to_string()));
+        }
+        if selected.vary {
+            fields.push(("Vary".to_string(), "Accept-Encoding".to_string()));
+        }
+        if let Some(encoding) = selected.encoding {
+            fields.push(("Content-Encoding".to_string(), encoding.to_string()));
+        }
+        if not_modified(request, &etag, modified) {
+            let mut response = HttpResponse::new(StatusCode::NotModified, None);
+            response.fields = fields;
+            return response;
+        }
+
+        let len = selected.len;
+        let ranges = match request.field("Range") {
+            Some(value)
+                if request.method == Method::Get && if_range(request, &etag, &last_modified) =>
+            {
+                range::parse(value, len)
+            }
+            _ => RangeRequest::Ignore,
+        };
+        let mut response = match ranges {
+            RangeRequest::Ignore => {
+                let contents = match selected.contents.bytes() {
+                    Some(bytes) => bytes.to_vec(),
+                    None => match fs::read(&selected.path) {
+                        Ok(c) => c,
+                        Err(e) => {
+                            warn!("fs::read({}) - {}", selected.path.display(), e);
+                            return HttpResponse::new(StatusCode::NotFound, None);
+                        }
+                    },
+                };
+                let mut response = HttpResponse::new(StatusCode::OK, Some(contents));
+                response
+                    .fields
+                    .push(("Content-Type".to_string(), content_type));
+                response
+            }
+            RangeRequest::Unsatisfiable => range::unsatisfiable_response(len),
+            RangeRequest::Ranges(ranges) => {
+                // only the requested bytes of files that are not in memory
+                let parts = match selected.contents.bytes() {
+                    Some(bytes) => range::slice(bytes, &ranges),
+                    None => match fs::File::open(&selected.path)
+                        .and_then(|mut file| range::read(&mut file, &ranges))
+                    {
+                        Ok(parts) => parts,
+                        Err(e) => {
+                            warn!("reading ranges of {} - {}", selected.path.display(), e);
+                            return HttpResponse::new(StatusCode::NotFound, None);
+                        }
+                    },
+                };
+                range::partial_response(len, &ranges, parts, &content_type)
+            }
+        };
+        response
+            .fields
+            .push(("Accept-Ranges".to_string(), "bytes".to_string()));
+        response.fields.extend(fields);
+        // the type is known, so browsers need not guess
+        response
+            .fields
+            .push(("X-Content-Type-Options".to_string(), "nosniff".to_string()));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn get(path: &str, fields: &[(&str, &str)]) -> HttpRequest {
+        fields.iter().fold(
+            HttpRequest::new(Method::Get, path),
+            |request, (name, value)| request.with_field(name, value),
+        )
+    }
+
+    #[test]
+    fn test_content_type() {
+        let config = StaticConfig {
+            mime_types: vec![
+                (
+                    "TXT".to_string(),
+                    "text/plain; charset=us-ascii".to_string(),
+                ),
+                ("gltf".to_string(), "model/gltf+json".to_string()),
+            ],
+            ..StaticConfig::default()
+        };
+        let files = StaticFiles::new(Some("frontend"), &config, None);
+        let content_type = |path: &str| files.content_type(Path::new(path));
+        assert_eq!(content_type("/index.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("/INDEX.JS"), "text/javascript; charset=utf-8");
+        assert_eq!(content_type("/logo.svg"), "image/svg+xml; charset=utf-8");
+        assert_eq!(content_type("/font.woff2"), "font/woff2");
+        assert_eq!(content_type("/hacker.txt"), "text/plain; charset=us-ascii");
+        assert_eq!(content_type("/scene.gltf"), "model/gltf+json");
+        assert_eq!(content_type("/Makefile"), DEFAULT_MIME_TYPE);
+        assert_eq!(content_type("/.hidden/x"), DEFAULT_MIME_TYPE);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/", false), Ok(vec![]));
+        assert_eq!(
+            normalize("/a/./b//c/../d?x=/../..", false),
+            Ok(vec!["a".to_string(), "b".to_string(), "d".to_string()])
+        );
+        assert_eq!(
+            normalize("/my%20notes.txt", false),
+            Ok(vec!["my notes.txt".to_string()])
+        );
+        assert_eq!(normalize("/../config.json", false), Err(PathError::Escapes));
+        assert_eq!(
+            normalize("/a/%2e%2e/%2E%2E/etc", false),
+            Err(PathError::Escapes)
+        );
+        assert_eq!(
+            normalize("/a%2f..%2f..%2fb", false),
+            Err(PathError::Escapes)
+        );
+        assert_eq!(
+            normalize("/index.html%00.js", false),
+            Err(PathError::NulByte)
+        );
+        assert_eq!(normalize("/%zz", false), Err(PathError::BadEncoding));
+        assert_eq!(normalize("/%e2%82", false), Err(PathError::BadEncoding));
+        assert_eq!(normalize("/.git/config", false), Err(PathError::Hidden));
+        assert!(normalize("/.git/config", true).is_ok());
+        assert!(normalize("/.well-known/security.txt", false).is_ok());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("/index.html", "/index.html"));
+        assert!(!glob_match("/index.html", "/index.htm"));
+        assert!(glob_match("*.js", "/index.js"));
+        assert!(glob_match("/assets/*", "/assets/img/logo.png"));
+        assert!(glob_match("/*/*.css", "/a/b/site.css"));
+        assert!(!glob_match("/*/*.css", "/site.css"));
+        assert!(glob_match("*", "/anything"));
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let request = |name: &str, value: &str| get("/index.js", &[(name, value)]);
+        let etag = "\"1f-5\"";
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let modified = 784111777;
+        assert!(not_modified(
+            &request("If-None-Match", "\"a\", W/\"1f-5\""),
+            etag,
+            modified
+        ));
+        assert!(not_modified(&request("If-None-Match", "*"), etag, modified));
+        assert!(!not_modified(
+            &request("If-None-Match", "\"a\""),
+            etag,
+            modified
+        ));
+        let compressed = "\"1f-5-gzip\"";
+        assert!(not_modified(
+            &request("If-None-Match", compressed),
+            etag,
+            modified
+        ));
+        let since = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert!(not_modified(
+            &request("If-Modified-Since", since),
+            etag,
+            modified
+        ));
+        assert!(!not_modified(
+            &request("If-Modified-Since", since),
+            etag,
+            modified + 1
+        ));
+        assert!(!not_modified(
+            &request("If-Modified-Since", "yesterday"),
+            etag,
+            modified
+        ));
+    }
+
+    #[test]
+    fn test_looks_like_file() {
+        assert!(looks_like_file("/index.js"));
+        assert!(looks_like_file("/assets/logo.svg?v=2"));
+        assert!(!looks_like_file("/notes/42"));
+        assert!(!looks_like_file("/notes/"));
+        assert!(!looks_like_file("/v1.2/notes"));
+    }
+
+    #[test]
+    fn test_fallback_and_error_pages() {
+        let root = env::temp_dir().join(format!("static_files_test_{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.html"), "app").unwrap();
+        fs::write(root.join("404.html"), "not found").unwrap();
+        fs::write(root.join("500.txt"), "broken").unwrap();
+        let config = StaticConfig {
+            error_pages: vec![(404, "404.html".to_string()), (500, "500.txt".to_string())],
+            fallback: Some("index.html".to_string()),
+            ..StaticConfig::default()
+        };
+        let files = StaticFiles::new(root.to_str(), &config, None);
+        let body = |response: &HttpResponse| response.body.clone().map(String::from_utf8);
+
+        // client-side routes get the app, missing assets a 404
+        let response = files.serve(&get("/notes/42", &[]));
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(body(&response), Some(Ok("app".to_string())));
+        let mut response = files.serve(&get("/missing.js", &[]));
+        assert_eq!(response.status_code, StatusCode::NotFound);
+        assert_eq!(response.body, None);
+
+        files.error_page(&mut response);
+        assert_eq!(body(&response), Some(Ok("not found".to_string())));
+        assert_eq!(
+            response.field("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        // e.g. from a panicking handler, which never reaches `serve`
+        let mut response = HttpResponse::new(StatusCode::InternalError, None);
+        files.error_page(&mut response);
+        assert_eq!(body(&response), Some(Ok("broken".to_string())));
+        let mut response = HttpResponse::new(StatusCode::ServiceUnavailable, None);
+        files.error_page(&mut response);
+        assert_eq!(response.body, None);
+        let mut response = HttpResponse::new(StatusCode::NotFound, Some(b"own".to_vec()));
+        files.error_page(&mut response);
+        assert_eq!(body(&response), Some(Ok("own".to_string())));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_sidecar_outside_root() {
+        let dir = env::temp_dir().join(format!("static_files_sidecars_{}", process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("app.js"), "app").unwrap();
+        fs::write(dir.join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret"), root.join("app.js.gz")).unwrap();
+        let request = get("/app.js", &[("Accept-Encoding", "gzip")]);
+
+        let mut config = StaticConfig {
+            precompressed: true,
+            ..StaticConfig::default()
+        };
+        let files = StaticFiles::new(root.to_str(), &config, None);
+        let response = files.serve(&request);
+        assert_eq!(response.body, Some(b"app".to_vec()));
+        assert_eq!(response.field("Content-Encoding"), None);
+        config.symlinks_outside_root = true;
+        let files = StaticFiles::new(root.to_str(), &config, None);
+        let response = files.serve(&request);
+        assert_eq!(response.body, Some(b"secret".to_vec()));
+        assert_eq!(response.field("Content-Encoding"), Some("gzip"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ranges_past_cache() {
+        let root = env::temp_dir().join(format!("static_files_ranges_{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let big: Vec<u8> = (0..100).collect();
+        fs::write(root.join("big.bin"), &big).unwrap();
+        fs::write(root.join("small.bin"), [1, 2, 3]).unwrap();
+        let config = StaticConfig {
+            cache: Some(CacheConfig {
+                max_bytes: 1000,
+                max_file_bytes: 10,
+            }),
+            ..StaticConfig::default()
+        };
+        let files = StaticFiles::new(root.to_str(), &config, None);
+        let root = fs::canonicalize(&root).unwrap();
+
+        let response = files.serve(&get("/big.bin", &[("Range", "bytes=10-19")]));
+        assert_eq!(response.status_code, StatusCode::PartialContent);
+        assert_eq!(response.body, Some(big[10..20].to_vec()));
+        // too big to cache, so left on disk for the ranges to be read from
+        let cache = files.cache.as_ref().unwrap();
+        assert_eq!(cache.sidecars(&root.join("big.bin")), None);
+        let selected = files.select(&get("/big.bin", &[]), &root.join("big.bin"));
+        assert!(matches!(selected.map(|s| s.contents), Ok(Contents::Disk)));
+
+        files.serve(&get("/small.bin", &[]));
+        assert_eq!(cache.sidecars(&root.join("small.bin")), Some(vec![]));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use crate::date::DateTime;
+use crate::types::{HttpRequest, HttpResponse};
+use log::warn;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
+
+const COMMON: &str = "%h %l %u %t \"%r\" %>s %b";
+const COMBINED: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// `%h` or `%a`
+    ClientAddr,
+    /// `%l` and `%u`, which we never know
+    Unknown,
+    /// `%t`
+    Time,
+    /// `%r`
+    RequestLine,
+    /// `%s` or `%>s`
+    Status,
+    /// `%b`, `-` for an empty body
+    Bytes,
+    /// `%B`
+    BytesZero,
+    /// `%D`
+    Micros,
+    /// `%T`
+    Seconds,
+    /// `%m`
+    Method,
+    /// `%U`
+    Path,
+    /// `%q`, including the `?`
+    Query,
+    /// `%H`
+    Protocol,
+    /// `%v`
+    Host,
+    /// `%L`
+    RequestId,
+    /// `%{Name}i`
+    Field(String),
+}
+
+/// An access log line template using the Apache `LogFormat` directives
+/// listed on `Part`, or one of the names `common` and `combined`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFormat {
+    parts: Vec<Part>,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        let template = match s {
+            "common" => COMMON,
+            "combined" => COMBINED,
+            s => s,
+        };
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let mut directive = chars.next();
+            if directive == Some('>') {
+                directive = chars.next();
+            }
+            let part = match directive {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('h') | Some('a') => Part::ClientAddr,
+                Some('l') | Some('u') => Part::Unknown,
+                Some('t') => Part::Time,
+                Some('r') => Part::RequestLine,
+                Some('s') => Part::Status,
+                Some('b') => Part::Bytes,
+                Some('B') => Part::BytesZero,
+                Some('D') => Part::Micros,
+                Some('T') => Part::Seconds,
+                Some('m') => Part::Method,
+                Some('U') => Part::Path,
+                Some('q') => Part::Query,
+                Some('H') => Part::Protocol,
+                Some('v') => Part::Host,
+                Some('L') => Part::RequestId,
+                Some('{') => {
+                    let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    match chars.next() {
+                        Some('i') => Part::Field(name),
+                        _ => return Err(format!("unsupported directive `%{{{}}}`", name)),
+                    }
+                }
+                Some(c) => return Err(format!("unsupported directive `%{}`", c)),
+                None => return Err("template ends in `%`".to_string()),
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(LogFormat { parts })
+    }
+}
+
+/// What is logged about one exchange. Taken when the request has been
+/// parsed, and written once the whole response has been sent.
+pub struct AccessLogEntry {
+    request: HttpRequest,
+    received: SystemTime,
+    start: Instant,
+    status: u16,
+    bytes: usize,
+}
+
+impl AccessLogEntry {
+    pub fn new(request: &HttpRequest) -> AccessLogEntry {
+        AccessLogEntry {
+            request: HttpRequest {
+                body: None,
+                ..request.clone()
+            },
+            received: SystemTime::now(),
+            start: Instant::now(),
+            status: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn set_response(&mut self, response: &HttpResponse) {
+        self.status = response.status_code.code();
+        self.bytes = response.body.as_ref().map_or(0, |b| b.len());
+    }
+}
+
+// request lines and fields are client controlled, so keep them on one
+// line and inside their quotes
+fn escape(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+impl LogFormat {
+    pub fn format(&self, entry: &AccessLogEntry) -> String {
+        let request = &entry.request;
+        let (path, query) = match request.path.split_once('?') {
+            Some((p, q)) => (p, Some(q)),
+            None => (request.path.as_str(), None),
+        };
+        let elapsed = entry.start.elapsed();
+        let mut line = String::new();
+        for part in &self.parts {
+            // writing to a String cannot fail
+            let _ = match part {
+                Part::Literal(s) => write!(line, "{}", s),
+                Part::ClientAddr => write!(line, "{}", request.client_addr),
+                Part::Unknown => write!(line, "-"),
+                Part::Time => write!(
+                    line,
+                    "[{}]",
+                    DateTime::from_system_time(entry.received).common_log()
+                ),
+                Part::RequestLine => {
+                    let request_line =
+                        format!("{} {} {}", request.method, request.path, request.version);
+                    escape(&mut line, &request_line);
+                    Ok(())
+                }
+                Part::Status => write!(line, "{}", entry.status),
+                Part::Bytes if entry.bytes == 0 => write!(line, "-"),
+                Part::Bytes | Part::BytesZero => write!(line, "{}", entry.bytes),
+                Part::Micros => write!(line, "{}", elapsed.as_micros()),
+                Part::Seconds => write!(line, "{}", elapsed.as_secs()),
+                Part::Method => write!(line, "{}", request.method),
+                Part::Path => {
+                    escape(&mut line, path);
+                    Ok(())
+                }
+                Part::Query => {
+                    if let Some(q) = query {
+                        line.push('?');
+                        escape(&mut line, q);
+                    }
+                    Ok(())
+                }
+                Part::Protocol => write!(line, "{}", request.version),
+                Part::Host => {
+                    escape(&mut line, request.host.as_deref().unwrap_or("-"));
+                    Ok(())
+                }
+                Part::RequestId => write!(line, "{}", request.id),
+                Part::Field(name) => {
+                    escape(&mut line, request.field(name).unwrap_or("-"));
+                    Ok(())
+                }
+            };
+        }
+        line.push('\n');
+        line
+    }
+}
+
+/// Appends one line per completed response to its own file.
+pub struct AccessLog {
+    file: File,
+    format: LogFormat,
+}
+
+impl AccessLog {
+    pub fn open(path: &str, format: LogFormat) -> io::Result<AccessLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog { file, format })
+    }
+
+    pub fn record(&self, entry: &AccessLogEntry) {
+        // one write per line, so lines stay whole even if the file is shared
+        let line = self.format.format(entry);
+        if let Err(e) = (&self.file).write_all(line.as_bytes()) {
+            warn!("could not write access log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Method, StatusCode};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry() -> AccessLogEntry {
+        let mut request = HttpRequest::new(Method::Get, "/apache_pb.gif?x=1")
+            .with_peer("127.0.0.1:5000".parse().unwrap())
+            .with_field("Referer", "http://example.com/")
+            .with_field("User-Agent", "Mozilla/4.08 \"x\"");
+        request.version = "HTTP/1.0".to_string();
+        request.id = "1".to_string();
+        let mut entry = AccessLogEntry::new(&request);
+        entry.received = UNIX_EPOCH + Duration::from_secs(971186136);
+        entry.set_response(&HttpResponse::new(StatusCode::OK, Some(vec![0; 2326])));
+        entry
+    }
+
+    #[test]
+    fn test_combined() {
+        let format: LogFormat = "combined".parse().unwrap();
+        assert_eq!(
+            format.format(&entry()),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?x=1 HTTP/1.0\" \
+             200 2326 \"http://example.com/\" \"Mozilla/4.08 \\\"x\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let format: LogFormat = "%L %m %U%q %{X-Missing}i 100%%".parse().unwrap();
+        assert_eq!(format.format(&entry()), "1 GET /apache_pb.gif?x=1 - 100%\n");
+        assert!("%Z".parse::<LogFormat>().is_err());
+        assert!("%{Referer}o".parse::<LogFormat>().is_err());
+    }
+}
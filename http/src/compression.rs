@@ -0,0 +1,238 @@
+use crate::CompressionConfig;
+use crate::types::{HttpResponse, StatusCode};
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use log::warn;
+use std::io::{self, Write};
+
+/// Encodings the server compresses with, preferred first.
+pub const ENCODINGS: [&str; 2] = ["gzip", "deflate"];
+
+/// Whether a response body of this type and size is worth compressing.
+pub fn worthwhile(content_type: &str, len: usize, config: &CompressionConfig) -> bool {
+    len >= config.min_size && compressible(content_type)
+}
+
+/// Picks the encoding from `available` (preferred first) that the client
+/// rates highest in its `Accept-Encoding`, or `None` for no encoding.
+pub fn negotiate<'a>(accept_encoding: &str, available: &[&'a str]) -> Option<&'a str> {
+    let mut rated: Vec<(&str, f32)> = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        let mut q = 1.0;
+        for param in params {
+            if let Some((name, value)) = param.split_once('=')
+                && name.trim().eq_ignore_ascii_case("q")
+            {
+                q = value.trim().parse().unwrap_or(0.0);
+            }
+        }
+        if !coding.is_empty() {
+            rated.push((coding, q));
+        }
+    }
+    let rating = |encoding: &str| {
+        let named = rated
+            .iter()
+            .find(|(coding, _)| coding.eq_ignore_ascii_case(encoding));
+        let any = rated.iter().find(|(coding, _)| *coding == "*");
+        named.or(any).map_or(0.0, |(_, q)| *q)
+    };
+    let mut best: Option<(&'a str, f32)> = None;
+    for &encoding in available {
+        let q = rating(encoding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Text-like types; images, fonts, media and archives are compressed already.
+pub fn compressible(content_type: &str) -> bool {
+    let mime_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    mime_type.starts_with("text/")
+        || mime_type.ends_with("+json")
+        || mime_type.ends_with("+xml")
+        || matches!(
+            mime_type.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "application/wasm"
+        )
+}
+
+/// `body` in `encoding`, one of `ENCODINGS`.
+pub fn encode(encoding: &str, body: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    let level = Compression::new(level);
+    match encoding {
+        "gzip" => {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        _ => {
+            // HTTP's "deflate" is the zlib format
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
+
+fn eligible(response: &HttpResponse, config: &CompressionConfig) -> bool {
+    let Some(body) = &response.body else {
+        return false;
+    };
+    // partial content is a slice of one particular encoding
+    response.status_code == StatusCode::OK
+        && response.field("Content-Encoding").is_none()
+        && response.field("Content-Range").is_none()
+        && response
+            .field("Content-Type")
+            .is_some_and(|t| worthwhile(t, body.len(), config))
+}
+
+fn add_vary(response: &mut HttpResponse) {
+    let vary = response
+        .fields
+        .iter_mut()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Vary"));
+    match vary {
+        Some((_, value)) => {
+            let listed = value
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("Accept-Encoding") || v.trim() == "*");
+            if !listed {
+                value.push_str(", Accept-Encoding");
+            }
+        }
+        None => response
+            .fields
+            .push(("Vary".to_string(), "Accept-Encoding".to_string())),
+    }
+}
+
+/// Compresses `response` in place if its type and size make that worth
+/// it and the client accepts one of our encodings.
+pub fn compress(
+    response: &mut HttpResponse,
+    accept_encoding: Option<&str>,
+    config: &CompressionConfig,
+) {
+    if !eligible(response, config) {
+        return;
+    }
+    // caches must not hand the compressed body to other clients
+    add_vary(response);
+    let Some(encoding) = accept_encoding.and_then(|a| negotiate(a, &ENCODINGS)) else {
+        return;
+    };
+    let body = response.body.as_deref().unwrap_or_default();
+    let compressed = match encode(encoding, body, config.level) {
+        Ok(c) if c.len() < body.len() => c,
+        Ok(_) => return,
+        Err(e) => {
+            warn!("could not compress response: {}", e);
+            return;
+        }
+    };
+    response.body = Some(compressed);
+    response
+        .fields
+        .push(("Content-Encoding".to_string(), encoding.to_string()));
+    // a strong ETag names these exact bytes, which just changed
+    for (name, value) in &mut response.fields {
+        if name.eq_ignore_ascii_case("ETag") {
+            *value = compressed_etag(value, encoding);
+        }
+    }
+}
+
+/// The ETag for `etag`'s content in `encoding`, e.g. `"abc-gzip"`.
+pub fn compressed_etag(etag: &str, encoding: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(tag) => format!("{}-{}\"", tag, encoding),
+        None => etag.to_string(),
+    }
+}
+
+/// The ETag `compress` started from, given one it produced: clients
+/// revalidate with the tag they were sent.
+pub fn uncompressed_etag(etag: &str) -> String {
+    for encoding in ENCODINGS {
+        if let Some(tag) = etag.strip_suffix(&format!("-{}\"", encoding)) {
+            return format!("{}\"", tag);
+        }
+    }
+    etag.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br", &ENCODINGS), Some("gzip"));
+        assert_eq!(negotiate("deflate", &ENCODINGS), Some("deflate"));
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate", &ENCODINGS),
+            Some("deflate")
+        );
+        assert_eq!(negotiate("gzip;q=0, *", &ENCODINGS), Some("deflate"));
+        assert_eq!(negotiate("identity", &ENCODINGS), None);
+        assert_eq!(negotiate("*;q=0", &ENCODINGS), None);
+        assert_eq!(negotiate("", &ENCODINGS), None);
+        assert_eq!(negotiate("GZIP", &ENCODINGS), Some("gzip"));
+        assert_eq!(negotiate("br, gzip", &["br", "gzip"]), Some("br"));
+    }
+
+    #[test]
+    fn test_compress() {
+        let config = CompressionConfig {
+            min_size: 100,
+            level: 6,
+        };
+        let text = "hello compression ".repeat(50);
+        let response = |content_type: &str, body: &str| {
+            let mut r = HttpResponse::new(StatusCode::OK, Some(body.as_bytes().to_vec()));
+            r.fields
+                .push(("Content-Type".to_string(), content_type.to_string()));
+            r.fields.push(("ETag".to_string(), "\"abc\"".to_string()));
+            r
+        };
+
+        let mut r = response("text/html; charset=utf-8", &text);
+        compress(&mut r, Some("gzip, deflate"), &config);
+        assert_eq!(r.field("Content-Encoding"), Some("gzip"));
+        assert_eq!(r.field("Vary"), Some("Accept-Encoding"));
+        assert_eq!(r.field("ETag"), Some("\"abc-gzip\""));
+        assert_eq!(uncompressed_etag("\"abc-gzip\""), "\"abc\"");
+        let mut decoded = String::new();
+        GzDecoder::new(r.body.as_deref().unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        // not asked for: untouched, but still marked as varying
+        let mut r = response("application/json", &text);
+        compress(&mut r, None, &config);
+        assert_eq!(r.field("Content-Encoding"), None);
+        assert_eq!(r.field("Vary"), Some("Accept-Encoding"));
+
+        let mut r = response("image/png", &text);
+        compress(&mut r, Some("gzip"), &config);
+        assert_eq!(r.field("Content-Encoding"), None);
+
+        let mut r = response("text/plain", "too small");
+        compress(&mut r, Some("gzip"), &config);
+        assert_eq!(r.field("Content-Encoding"), None);
+    }
+}
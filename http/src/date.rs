@@ -0,0 +1,184 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+// Howard Hinnant's days_from_civil: days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// A UTC calendar time, to the second.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 to 12
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    pub fn now() -> DateTime {
+        DateTime::from_system_time(SystemTime::now())
+    }
+
+    /// Times before 1970 are clamped to the epoch.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+        // Howard Hinnant's days_from_civil, inverted
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u32,
+            minute: (rem / 60 % 60) as u32,
+            second: (rem % 60) as u32,
+        }
+    }
+
+    fn month_name(&self) -> &'static str {
+        MONTHS[(self.month - 1) as usize]
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn unix_time(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86400
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+    }
+
+    /// An HTTP-date (RFC 9110 IMF-fixdate), e.g. `Tue, 10 Oct 2000 13:55:36 GMT`.
+    pub fn http_date(&self) -> String {
+        let days = days_from_civil(self.year, self.month, self.day);
+        // 1970-01-01 was a Thursday
+        let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            weekday,
+            self.day,
+            self.month_name(),
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// Parses an IMF-fixdate. The obsolete RFC 850 and asctime forms are
+    /// not accepted; a conditional request with one just gets a full response.
+    pub fn parse_http_date(s: &str) -> Option<DateTime> {
+        let (weekday, rest) = s.split_once(", ")?;
+        if !WEEKDAYS.contains(&weekday) {
+            return None;
+        }
+        let parts: Vec<&str> = rest.split(' ').collect();
+        let [day, month, year, time, "GMT"] = parts[..] else {
+            return None;
+        };
+        let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+        let time: Vec<u32> = time
+            .split(':')
+            .map(|n| n.parse().ok())
+            .collect::<Option<_>>()?;
+        let [hour, minute, second] = time[..] else {
+            return None;
+        };
+        let date = DateTime {
+            year: year.parse().ok()?,
+            month,
+            day: day.parse().ok()?,
+            hour,
+            minute,
+            second,
+        };
+        if date.day == 0 || date.day > 31 || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        Some(date)
+    }
+
+    /// As in the Common Log Format, e.g. `10/Oct/2000:13:55:36 +0000`.
+    pub fn common_log(&self) -> String {
+        format!(
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            self.day,
+            self.month_name(),
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// RFC 3339 in UTC, e.g. `2000-10-10T13:55:36Z`.
+    pub fn rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> DateTime {
+        DateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn test_from_system_time() {
+        assert_eq!(at(0).common_log(), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(at(971186136).common_log(), "10/Oct/2000:13:55:36 +0000");
+        // leap day
+        assert_eq!(at(1709210096).common_log(), "29/Feb/2024:12:34:56 +0000");
+        assert_eq!(at(971186136).rfc3339(), "2000-10-10T13:55:36Z");
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(at(784111777).http_date(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(at(1709210096).http_date(), "Thu, 29 Feb 2024 12:34:56 GMT");
+        let parsed = DateTime::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parsed, Some(at(784111777)));
+        assert_eq!(parsed.map(|d| d.unix_time()), Some(784111777));
+        assert_eq!(at(1709210096).unix_time(), 1709210096);
+        assert_eq!(
+            DateTime::parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            None
+        );
+        assert_eq!(
+            DateTime::parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"),
+            None
+        );
+        assert_eq!(
+            DateTime::parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"),
+            None
+        );
+    }
+}
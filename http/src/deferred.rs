@@ -0,0 +1,158 @@
+use crate::types::{HttpResponse, StatusCode};
+use log::warn;
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// What a handler gives back from `HttpHandler::reply`.
+pub enum Reply {
+    Now(HttpResponse),
+    /// the response comes later through the `PendingResponse`
+    Later,
+}
+
+/// A promise to answer one request, which can be sent to other threads.
+/// Dropping it unanswered answers `500 Internal Server Error`.
+pub struct PendingResponse {
+    key: u64,
+    sender: Sender<(u64, HttpResponse)>,
+    wake: Arc<EventFd>,
+    /// set by the server when the client hangs up
+    cancelled: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl PendingResponse {
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Whether the client has gone away, so there is no one left to answer.
+    /// Holders can then `forget` it rather than keep it around.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    pub fn complete(mut self, response: HttpResponse) {
+        self.send(response);
+    }
+
+    /// Gives up the handle without answering, for a handler that returns
+    /// `Reply::Now` after all.
+    pub fn forget(mut self) {
+        self.done = true;
+    }
+
+    fn send(&mut self, response: HttpResponse) {
+        self.done = true;
+        // the server is gone if this fails, and with it the connection
+        if self.sender.send((self.key, response)).is_ok()
+            && let Err(e) = self.wake.write(1)
+        {
+            warn!("could not wake server: {}", e);
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        if !self.done {
+            warn!("pending response dropped without an answer");
+            self.send(HttpResponse::new(StatusCode::InternalError, None));
+        }
+    }
+}
+
+/// The server's end: hands out `PendingResponse`s and collects what they
+/// are completed with. Its fd becomes readable when there is something
+/// to collect.
+pub struct Completions {
+    sender: Sender<(u64, HttpResponse)>,
+    receiver: Receiver<(u64, HttpResponse)>,
+    wake: Arc<EventFd>,
+    next_key: u64,
+}
+
+impl Completions {
+    pub fn new() -> nix::Result<Completions> {
+        let (sender, receiver) = mpsc::channel();
+        let wake = EventFd::from_flags(EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC)?;
+        Ok(Completions {
+            sender,
+            receiver,
+            wake: Arc::new(wake),
+            next_key: 0,
+        })
+    }
+
+    pub fn pending(&mut self) -> PendingResponse {
+        self.next_key += 1;
+        PendingResponse {
+            key: self.next_key,
+            sender: self.sender.clone(),
+            wake: self.wake.clone(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            done: false,
+        }
+    }
+
+    /// Responses completed since the last call, by key.
+    pub fn take(&self) -> Vec<(u64, HttpResponse)> {
+        // EAGAIN just means nothing was written since the last read
+        let _ = self.wake.read();
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl AsFd for Completions {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.wake.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_completions() {
+        let mut completions = Completions::new().unwrap();
+        let (a, b, c) = (
+            completions.pending(),
+            completions.pending(),
+            completions.pending(),
+        );
+        let (key_a, key_b) = (a.key(), b.key());
+        assert!(completions.take().is_empty());
+
+        thread::spawn(move || a.complete(HttpResponse::new(StatusCode::OK, None)))
+            .join()
+            .unwrap();
+        // unanswered is an error, forgotten is nothing at all
+        drop(b);
+        c.forget();
+        let taken: Vec<(u64, StatusCode)> = completions
+            .take()
+            .into_iter()
+            .map(|(key, response)| (key, response.status_code))
+            .collect();
+        assert_eq!(
+            taken,
+            [(key_a, StatusCode::OK), (key_b, StatusCode::InternalError)]
+        );
+        assert!(completions.take().is_empty());
+
+        let d = completions.pending();
+        assert!(!d.is_cancelled());
+        d.cancel_flag().store(true, Ordering::Relaxed);
+        assert!(d.is_cancelled());
+        d.forget();
+    }
+}
@@ -0,0 +1,277 @@
+use crate::types::{HttpRequest, Scheme};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+const FORWARDING_FIELDS: [&str; 4] = [
+    "Forwarded",
+    "X-Forwarded-For",
+    "X-Forwarded-Proto",
+    "X-Forwarded-Host",
+];
+
+/// An address block such as `10.0.0.0/8` or `::1/128`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // IPv4 peers on a dual-stack socket show up as ::ffff:a.b.c.d
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = &'static str;
+
+    /// A bare address is a block of one.
+    fn from_str(s: &str) -> Result<Cidr, &'static str> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let addr: IpAddr = match addr.parse() {
+            Ok(a) => a,
+            Err(_) => return Err("invalid address"),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix.map(|p| p.parse::<u8>()) {
+            None => max,
+            Some(Ok(p)) if p <= max => p,
+            Some(_) => return Err("invalid prefix length"),
+        };
+        Ok(Cidr {
+            addr: addr.to_canonical(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+fn is_trusted(ip: &IpAddr, trusted: &[Cidr]) -> bool {
+    trusted.iter().any(|c| c.contains(ip))
+}
+
+// all comma separated values of every `name` field, in order
+fn field_list(request: &HttpRequest, name: &str) -> Vec<String> {
+    request
+        .fields
+        .iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+        .flat_map(|(_, v)| v.split(','))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn unquote(s: &str) -> &str {
+    s.trim()
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s.trim())
+}
+
+/// Parses a node as found in `X-Forwarded-For` or a `for=` parameter:
+/// `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` or `[2001:db8::1]:4711`.
+/// Obfuscated identifiers and `unknown` give `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = unquote(node);
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    node.rsplit_once(':')?.0.parse().ok()
+}
+
+fn parse_scheme(s: &str) -> Option<Scheme> {
+    match unquote(s).to_ascii_lowercase().as_str() {
+        "http" => Some(Scheme::Http),
+        "https" => Some(Scheme::Https),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct ForwardedElement {
+    node: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+// one element of an RFC 7239 `Forwarded` field, e.g. `for=192.0.2.60;proto=http`
+fn parse_forwarded_element(element: &str) -> ForwardedElement {
+    let mut parsed = ForwardedElement::default();
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = Some(unquote(value).to_string());
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => parsed.node = value,
+            "proto" => parsed.proto = value,
+            "host" => parsed.host = value,
+            _ => {}
+        }
+    }
+    parsed
+}
+
+/// Replaces the client address, scheme and host of a request that came
+/// through trusted proxies with the values those proxies forwarded.
+///
+/// Hops are walked from the nearest proxy outwards and the first untrusted
+/// hop is taken as the client, so addresses a client prepends itself are
+/// never believed. `Forwarded` is preferred over the `X-Forwarded-*` fields.
+/// Requests from untrusted peers have the forwarding fields removed.
+pub fn apply(request: &mut HttpRequest, trusted: &[Cidr]) {
+    let peer = request.connection.peer_addr.ip();
+    if !is_trusted(&peer, trusted) {
+        request
+            .fields
+            .retain(|(k, _)| !FORWARDING_FIELDS.iter().any(|f| k.eq_ignore_ascii_case(f)));
+        return;
+    }
+
+    let mut client = peer;
+    let mut scheme = None;
+    let mut host = None;
+    let forwarded = field_list(request, "Forwarded");
+    if !forwarded.is_empty() {
+        for element in forwarded.iter().rev().map(|e| parse_forwarded_element(e)) {
+            if !is_trusted(&client, trusted) {
+                break;
+            }
+            // each element describes the connection into the proxy that
+            // added it, so the outermost trusted one saw the original request
+            if let Some(p) = element.proto.as_deref().and_then(parse_scheme) {
+                scheme = Some(p);
+            }
+            if element.host.is_some() {
+                host = element.host;
+            }
+            match element.node.as_deref().and_then(parse_node) {
+                Some(ip) => client = ip,
+                None => break,
+            }
+        }
+    } else {
+        for node in field_list(request, "X-Forwarded-For").iter().rev() {
+            if !is_trusted(&client, trusted) {
+                break;
+            }
+            match parse_node(node) {
+                Some(ip) => client = ip,
+                None => break,
+            }
+        }
+        // when proxies append rather than overwrite, the last value is the
+        // one our own proxy added
+        scheme = field_list(request, "X-Forwarded-Proto")
+            .last()
+            .and_then(|p| parse_scheme(p));
+        host = field_list(request, "X-Forwarded-Host").pop();
+    }
+
+    request.client_addr = client;
+    if let Some(scheme) = scheme {
+        request.scheme = scheme;
+    }
+    if host.is_some() {
+        request.host = host;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Method;
+
+    fn request(peer: &str, fields: &[(&str, &str)]) -> HttpRequest {
+        let request = HttpRequest::new(Method::Get, "/")
+            .with_peer(peer.parse().unwrap())
+            .with_host("backend:7878");
+        fields.iter().fold(request, |request, (name, value)| {
+            request.with_field(name, value)
+        })
+    }
+
+    fn trusted() -> Vec<Cidr> {
+        vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(cidr.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains(&"1.2.3.4".parse().unwrap())
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_x_forwarded() {
+        let mut req = request(
+            "10.0.0.2:5000",
+            &[
+                ("X-Forwarded-For", "198.51.100.7, 203.0.113.9, 10.0.0.5"),
+                ("x-forwarded-proto", "https"),
+                ("X-Forwarded-Host", "example.com"),
+            ],
+        );
+        apply(&mut req, &trusted());
+        assert_eq!(req.client_addr, "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(req.scheme, Scheme::Https);
+        assert_eq!(req.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let mut req = request(
+            "[::1]:5000",
+            &[(
+                "Forwarded",
+                "for=192.0.2.60;proto=https;host=example.com, for=\"[2001:db8::1]:4711\"",
+            )],
+        );
+        apply(&mut req, &trusted());
+        assert_eq!(req.client_addr, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(req.scheme, Scheme::Http);
+    }
+
+    #[test]
+    fn test_untrusted_peer() {
+        let mut req = request(
+            "198.51.100.7:5000",
+            &[("X-Forwarded-For", "10.0.0.1"), ("Accept", "*/*")],
+        );
+        apply(&mut req, &trusted());
+        assert_eq!(req.client_addr, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(req.fields.len(), 1);
+    }
+}
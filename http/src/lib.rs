@@ -1,16 +1,145 @@
+pub mod access_log;
+pub mod compression;
+pub mod date;
+pub mod deferred;
+pub mod forwarded;
+mod limits;
+pub mod metrics;
 mod parser;
+mod proxy_protocol;
+pub mod range;
+pub mod rate_limit;
+pub mod request_id;
 pub mod server;
 pub mod socket;
+pub mod tls;
 pub mod types;
 
+use access_log::LogFormat;
+use forwarded::Cidr;
+
 #[derive(Debug)]
 pub struct ServerConfig {
     pub address: String,
     pub tls: Option<TlsConfig>,
+    /// expect a PROXY protocol v1 or v2 header on every connection
+    pub proxy_protocol: bool,
+    /// peers whose `Forwarded` and `X-Forwarded-*` fields are believed
+    pub trusted_proxies: Vec<Cidr>,
+    pub limits: ConnectionLimits,
+    pub access_log: Option<AccessLogConfig>,
+    /// compress responses the client accepts compressed; `None` disables it
+    pub compression: Option<CompressionConfig>,
+}
+
+#[derive(Debug)]
+pub struct AccessLogConfig {
+    pub path: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// bodies smaller than this are sent as they are
+    pub min_size: usize,
+    /// 0 (fastest) to 9 (smallest)
+    pub level: u32,
+}
+
+/// Bounds on concurrently open connections; 0 means unlimited.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionLimits {
+    pub max_connections: usize,
+    /// counted per client address; for connections through a PROXY
+    /// protocol load balancer or a trusted proxy, once the request says
+    /// which client they are for
+    pub max_per_ip: usize,
+    pub policy: OverloadPolicy,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OverloadPolicy {
+    /// leave new connections in the listen backlog until a slot frees up;
+    /// connections over the per-IP limit are closed right away
+    StopAccepting,
+    /// accept, answer `503 Service Unavailable` and close
+    Reject,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// checked in order, the first route whose path prefix matches applies
+    pub routes: Vec<RouteLimit>,
+    /// bound on the number of buckets kept in memory
+    pub max_clients: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteLimit {
+    pub path: String,
+    /// requests allowed at once before the rate applies
+    pub burst: u32,
+    pub per_second: f64,
+    pub key: RateLimitKey,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RateLimitKey {
+    ClientAddr,
+    /// the limiter's key function, falling back to the client address
+    Custom,
 }
 
 #[derive(Debug)]
 pub struct TlsConfig {
     pub cert: String,
     pub key: String,
+    /// certificates for other server names, chosen by SNI; `cert` is sent
+    /// when the client asks for none of them
+    pub sni: Vec<SniCertificate>,
+    pub client_auth: Option<ClientAuthConfig>,
+    pub min_version: TlsVersion,
+    /// IANA names, e.g. `TLS13_AES_256_GCM_SHA384`; empty keeps the rustls defaults
+    pub cipher_suites: Vec<String>,
+    pub alpn_protocols: Vec<String>,
+    pub session: SessionConfig,
+    /// NSS key log file for decrypting captured traffic; never set in production
+    pub key_log: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SniCertificate {
+    /// matched case-insensitively
+    pub names: Vec<String>,
+    pub cert: String,
+    pub key: String,
+}
+
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+#[derive(Debug)]
+pub struct SessionConfig {
+    /// issue stateless session tickets
+    pub tickets: bool,
+    /// number of sessions kept for stateful resumption, 0 disables it
+    pub cache_size: usize,
+}
+
+#[derive(Debug)]
+pub struct ClientAuthConfig {
+    /// PEM bundle of the CAs that may sign client certificates
+    pub ca: String,
+    pub mode: ClientAuthMode,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ClientAuthMode {
+    /// handshakes without a valid client certificate are rejected
+    Required,
+    /// clients may connect anonymously, but any certificate sent must verify
+    Optional,
 }
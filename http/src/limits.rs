@@ -0,0 +1,217 @@
+use crate::{ConnectionLimits, OverloadPolicy};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub enum Admission {
+    Accept,
+    /// over a limit, answer `503 Service Unavailable` and close
+    Reject,
+    /// over a limit, close without a response
+    Close,
+}
+
+/// Counts open connections in total and per client address.
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    counts: Rc<RefCell<Counts>>,
+}
+
+#[derive(Default)]
+struct Counts {
+    // kept alongside `per_ip` rather than summed, as it is asked for often
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Held by a connection for as long as it is open; dropping it frees the slot.
+pub struct ConnectionSlot {
+    ip: IpAddr,
+    /// whether `ip` is the client's and was held to the per-IP limit
+    ip_checked: bool,
+    limits: ConnectionLimits,
+    counts: Rc<RefCell<Counts>>,
+    admission: Admission,
+}
+
+impl Counts {
+    fn of_ip(&self, ip: IpAddr) -> usize {
+        self.per_ip.get(&ip).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, ip: IpAddr) {
+        self.total += 1;
+        *self.per_ip.entry(ip).or_insert(0) += 1;
+    }
+
+    fn remove(&mut self, ip: IpAddr) {
+        if let Some(n) = self.per_ip.get_mut(&ip) {
+            self.total -= 1;
+            *n -= 1;
+            if *n == 0 {
+                self.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+impl ConnectionLimits {
+    fn over_limit(&self) -> Admission {
+        match self.policy {
+            OverloadPolicy::Reject => Admission::Reject,
+            OverloadPolicy::StopAccepting => Admission::Close,
+        }
+    }
+
+    fn over_ip_limit(&self, from_ip: usize) -> bool {
+        self.max_per_ip != 0 && from_ip >= self.max_per_ip
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> ConnectionLimiter {
+        ConnectionLimiter {
+            limits,
+            counts: Rc::new(RefCell::new(Counts::default())),
+        }
+    }
+
+    pub fn open(&self) -> usize {
+        self.counts.borrow().total
+    }
+
+    /// With `OverloadPolicy::StopAccepting` the listener is left alone while
+    /// this is true, so new connections wait in the kernel backlog.
+    pub fn paused(&self) -> bool {
+        self.limits.policy == OverloadPolicy::StopAccepting && self.global_limit_reached()
+    }
+
+    fn global_limit_reached(&self) -> bool {
+        self.limits.max_connections != 0 && self.open() >= self.limits.max_connections
+    }
+
+    /// Counts a new connection from `ip` and decides what to do with it.
+    /// Rejected connections are counted too until they are closed.
+    pub fn admit(&self, ip: IpAddr) -> ConnectionSlot {
+        self.admit_from(ip, true)
+    }
+
+    /// Like `admit` for a connection from a proxy: only the global limit
+    /// applies until `ConnectionSlot::reassign` names the client.
+    pub fn admit_proxied(&self, ip: IpAddr) -> ConnectionSlot {
+        self.admit_from(ip, false)
+    }
+
+    fn admit_from(&self, ip: IpAddr, check_ip: bool) -> ConnectionSlot {
+        let ip = ip.to_canonical();
+        let from_ip = self.counts.borrow().of_ip(ip);
+        let over_ip_limit = check_ip && self.limits.over_ip_limit(from_ip);
+        let admission = if !over_ip_limit && !self.global_limit_reached() {
+            Admission::Accept
+        } else {
+            self.limits.over_limit()
+        };
+        self.counts.borrow_mut().add(ip);
+        ConnectionSlot {
+            ip,
+            ip_checked: check_ip,
+            limits: self.limits,
+            counts: self.counts.clone(),
+            admission,
+        }
+    }
+}
+
+impl ConnectionSlot {
+    pub fn admission(&self) -> &Admission {
+        &self.admission
+    }
+
+    /// Counts the connection against `ip`, the client a proxy turned out
+    /// to be connecting for, and holds it to that address's limit.
+    pub fn reassign(&mut self, ip: IpAddr) {
+        let ip = ip.to_canonical();
+        if self.ip_checked && ip == self.ip {
+            return;
+        }
+        let mut counts = self.counts.borrow_mut();
+        counts.remove(self.ip);
+        if self.admission == Admission::Accept && self.limits.over_ip_limit(counts.of_ip(ip)) {
+            self.admission = self.limits.over_limit();
+        }
+        counts.add(ip);
+        self.ip = ip;
+        self.ip_checked = true;
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.counts.borrow_mut().remove(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(
+        max_connections: usize,
+        max_per_ip: usize,
+        policy: OverloadPolicy,
+    ) -> ConnectionLimiter {
+        ConnectionLimiter::new(ConnectionLimits {
+            max_connections,
+            max_per_ip,
+            policy,
+        })
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let limiter = limiter(0, 2, OverloadPolicy::Reject);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+        let first = limiter.admit(a);
+        let _second = limiter.admit(a);
+        assert_eq!(limiter.admit(a).admission(), &Admission::Reject);
+        assert_eq!(limiter.admit(b).admission(), &Admission::Accept);
+        drop(first);
+        assert_eq!(limiter.admit(a).admission(), &Admission::Accept);
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let limiter = limiter(1, 0, OverloadPolicy::StopAccepting);
+        let slot = limiter.admit("::1".parse().unwrap());
+        assert!(limiter.paused());
+        assert_eq!(
+            limiter.admit("::2".parse().unwrap()).admission(),
+            &Admission::Close
+        );
+        drop(slot);
+        assert!(!limiter.paused());
+        assert_eq!(limiter.open(), 0);
+    }
+
+    #[test]
+    fn test_proxied() {
+        let limiter = limiter(0, 1, OverloadPolicy::Reject);
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut first = limiter.admit_proxied(proxy);
+        let mut second = limiter.admit_proxied(proxy);
+        assert_eq!(second.admission(), &Admission::Accept);
+        first.reassign("192.0.2.1".parse().unwrap());
+        second.reassign("192.0.2.2".parse().unwrap());
+        assert_eq!(first.admission(), &Admission::Accept);
+        assert_eq!(second.admission(), &Admission::Accept);
+        let mut third = limiter.admit_proxied(proxy);
+        third.reassign("192.0.2.1".parse().unwrap());
+        assert_eq!(third.admission(), &Admission::Reject);
+        assert_eq!(limiter.open(), 3);
+        drop((first, second, third));
+        assert_eq!(limiter.open(), 0);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::server::HttpHandler;
+use crate::types::{HttpRequest, HttpResponse, Method, StatusCode};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Process wide, so several servers report together.
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+struct Inner {
+    // (method, route, status)
+    requests: BTreeMap<(String, &'static str, u16), u64>,
+    durations: BTreeMap<&'static str, Histogram>,
+    received_bytes: u64,
+    sent_bytes: u64,
+    // by listener address
+    connections: BTreeMap<String, Connections>,
+    tls_handshake_failures: u64,
+}
+
+// name, help and how to read it
+type ConnectionGauge = (&'static str, &'static str, fn(&Connections) -> usize);
+
+#[derive(Default)]
+struct Connections {
+    parsers: usize,
+    responders: usize,
+    open: usize,
+}
+
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            inner: Mutex::new(Inner {
+                requests: BTreeMap::new(),
+                durations: BTreeMap::new(),
+                received_bytes: 0,
+                sent_bytes: 0,
+                connections: BTreeMap::new(),
+                tls_handshake_failures: 0,
+            }),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        // counters are still meaningful after a panic elsewhere
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_request(&self, method: &Method, route: &'static str, status: u16, took: Duration) {
+        let mut inner = self.inner();
+        *inner
+            .requests
+            .entry((method.to_string(), route, status))
+            .or_insert(0) += 1;
+        inner
+            .durations
+            .entry(route)
+            .or_default()
+            .observe(took.as_secs_f64());
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.inner().received_bytes += bytes as u64;
+    }
+
+    pub fn add_sent(&self, bytes: usize) {
+        self.inner().sent_bytes += bytes as u64;
+    }
+
+    pub fn tls_handshake_failed(&self) {
+        self.inner().tls_handshake_failures += 1;
+    }
+
+    pub fn set_connections(&self, listener: &str, parsers: usize, responders: usize, open: usize) {
+        let mut inner = self.inner();
+        let connections = inner.connections.entry(listener.to_string()).or_default();
+        connections.parsers = parsers;
+        connections.responders = responders;
+        connections.open = open;
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let inner = self.inner();
+        let mut out = String::new();
+        fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        }
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Responses sent, by method, route and status.",
+        );
+        for ((method, route, status), n) in &inner.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, status, n
+            );
+        }
+
+        let name = "http_request_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Time from parsing a request to sending the last byte of its response.",
+        );
+        for (route, histogram) in &inner.durations {
+            for (bound, n) in BUCKETS.iter().zip(histogram.counts) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    name, route, bound, n
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                name, route, histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{{route=\"{}\"}} {}", name, route, histogram.sum);
+            let _ = writeln!(
+                out,
+                "{}_count{{route=\"{}\"}} {}",
+                name, route, histogram.count
+            );
+        }
+
+        let single = [
+            (
+                "http_received_bytes_total",
+                "counter",
+                "Bytes read from clients, after decryption.",
+                inner.received_bytes,
+            ),
+            (
+                "http_sent_bytes_total",
+                "counter",
+                "Bytes written to clients, before encryption.",
+                inner.sent_bytes,
+            ),
+            (
+                "http_tls_handshake_failures_total",
+                "counter",
+                "TLS handshakes that failed or were abandoned.",
+                inner.tls_handshake_failures,
+            ),
+        ];
+        for (name, kind, help, value) in single {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let gauges: [ConnectionGauge; 3] = [
+            (
+                "http_active_parsers",
+                "Connections waiting for a complete request.",
+                |c| c.parsers,
+            ),
+            (
+                "http_active_responders",
+                "Responses still being written.",
+                |c| c.responders,
+            ),
+            (
+                "http_open_connections",
+                "Connections counted against the connection limits.",
+                |c| c.open,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            header(&mut out, name, "gauge", help);
+            for (listener, connections) in &inner.connections {
+                let _ = writeln!(
+                    out,
+                    "{}{{listener=\"{}\"}} {}",
+                    name,
+                    listener,
+                    value(connections)
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Carried with a response until it has been sent.
+pub struct RequestRecord {
+    method: Method,
+    route: &'static str,
+    status: u16,
+    start: Instant,
+}
+
+impl RequestRecord {
+    pub fn new(request: &HttpRequest, route: &'static str) -> RequestRecord {
+        RequestRecord {
+            method: request.method.clone(),
+            route,
+            status: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn set_status(&mut self, status: &StatusCode) {
+        self.status = status.code();
+    }
+
+    pub fn finish(self) {
+        METRICS.record_request(&self.method, self.route, self.status, self.start.elapsed());
+    }
+}
+
+/// Answers every request with the current metrics.
+pub struct MetricsHandler;
+
+impl HttpHandler for MetricsHandler {
+    fn handle(&self, _request: HttpRequest) -> HttpResponse {
+        let mut response = HttpResponse::new(StatusCode::OK, Some(METRICS.render().into_bytes()));
+        response.fields.push((
+            "Content-Type".to_string(),
+            "text/plain; version=0.0.4".to_string(),
+        ));
+        response
+    }
+
+    fn route(&self, _request: &HttpRequest) -> &'static str {
+        "metrics"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.record_request(&Method::Get, "/api/hello", 200, Duration::from_millis(30));
+        metrics.add_sent(12);
+        let text = metrics.render();
+        assert!(text.contains(
+            "http_requests_total{method=\"GET\",route=\"/api/hello\",status=\"200\"} 1\n"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{route=\"/api/hello\",le=\"0.025\"} 0\n"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{route=\"/api/hello\",le=\"0.05\"} 1\n"
+        ));
+        assert!(text.contains("http_sent_bytes_total 12\n"));
+    }
+}
@@ -1,9 +1,9 @@
+use crate::request_id;
 use crate::socket::Stream;
 use crate::types::*;
 use log::warn;
 use std::io::{BufRead, BufReader, ErrorKind, Read};
 use std::mem;
-use std::os::fd::{AsFd, BorrowedFd};
 use std::time::{Duration, SystemTime};
 
 enum HttpParserState {
@@ -32,13 +32,6 @@ pub struct AsyncHttpParser {
 }
 
 impl AsyncHttpParser {
-    pub fn as_fd(&self) -> BorrowedFd {
-        return self.reader.get_ref().as_fd();
-    }
-
-    pub fn get_stream(&mut self) -> &mut Stream {
-        self.reader.get_mut()
-    }
     pub fn into_stream(self) -> Stream {
         self.reader.into_inner()
     }
@@ -46,14 +39,14 @@ impl AsyncHttpParser {
     pub fn new(reader: BufReader<Stream>) -> AsyncHttpParser {
         AsyncHttpParser {
             state: HttpParserState::NotStarted,
-            reader: reader,
+            reader,
             timeout_info: None,
         }
     }
     pub fn set_timeout(&mut self, duration: Duration) {
         self.timeout_info = Some(TimeoutInfo {
             start: SystemTime::now(),
-            duration: duration,
+            duration,
         });
     }
 
@@ -81,7 +74,9 @@ impl AsyncHttpParser {
             warn!("Error reading line: {}", e.kind());
             return Future::Fail("Empty request");
         }
-        self.state = match parse_start(first_line) {
+        // the first line can only be read once any tls handshake is complete
+        let connection = self.reader.get_ref().connection_info();
+        self.state = match parse_start(first_line, connection) {
             Ok(state) => state,
             Err(e) => {
                 warn!("{}", e);
@@ -132,11 +127,7 @@ impl AsyncHttpParser {
         let HttpParserState::ParsingBody(_, length) = &self.state else {
             return Future::Fail("Unexpected state");
         };
-        let mut http_body = Vec::new();
-        http_body.reserve(*length);
-        unsafe {
-            http_body.set_len(*length);
-        }
+        let mut http_body = vec![0; *length];
         if let Err(e) = self.reader.read_exact(&mut http_body) {
             if let ErrorKind::WouldBlock = e.kind() {
                 return Future::Wait;
@@ -185,8 +176,10 @@ impl AsyncHttpParser {
     }
 }
 
-fn fields_end_state(request: HttpRequest) -> HttpParserState {
+fn fields_end_state(mut request: HttpRequest) -> HttpParserState {
     use HttpParserState::*;
+    request.host = request.field("Host").map(|h| h.to_string());
+    request.id = request_id::for_request(&request);
     match expected_content_length(&request) {
         Some(n) => ParsingBody(request, n),
         None => Done(request),
@@ -194,30 +187,24 @@ fn fields_end_state(request: HttpRequest) -> HttpParserState {
 }
 
 fn expected_content_length(request: &HttpRequest) -> Option<usize> {
-    let fields = &request.fields;
     if request.method != Method::Post {
         return None;
     }
-    let mut exp_len = 0;
-    for f in fields {
-        if f.0 == "Content-Length" {
-            exp_len = match f.1.parse::<usize>() {
-                Ok(n) => n,
-                Err(_) => {
-                    warn!("Unexpected Content-Length");
-                    0
-                }
-            };
-            break;
+    let exp_len = match request.field("Content-Length").map(|l| l.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            warn!("Unexpected Content-Length");
+            0
         }
-    }
+        None => 0,
+    };
     match exp_len {
         0 => None,
         n => Some(n),
     }
 }
 
-fn parse_start(line: String) -> Result<HttpParserState, &'static str> {
+fn parse_start(line: String, connection: ConnectionInfo) -> Result<HttpParserState, &'static str> {
     let mut words = line.split(' ');
     let method = match words.next() {
         Some("GET") => Method::Get,
@@ -238,18 +225,24 @@ fn parse_start(line: String) -> Result<HttpParserState, &'static str> {
     _ = version.pop();
 
     Ok(HttpParserState::ParsingFields(HttpRequest {
-        method: method,
-        path: path,
-        version: version,
+        method,
+        path,
+        version,
         fields: Vec::new(),
         body: None,
+        client_addr: connection.peer_addr.ip(),
+        scheme: match connection.tls {
+            Some(_) => Scheme::Https,
+            None => Scheme::Http,
+        },
+        host: None,
+        id: String::new(),
+        connection,
     }))
 }
 
 fn parse_field(line: String) -> Option<Field> {
-    let mut it = line.split(':');
-    let key = it.next()?.to_string();
-    let mut val = it.next()?.to_string();
-    val.remove(0);
-    Some((key, val))
+    // values may contain ':' themselves, e.g. `Host: localhost:7878`
+    let (key, val) = line.split_once(':')?;
+    Some((key.to_string(), val.trim().to_string()))
 }
@@ -0,0 +1,252 @@
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+// longest line the v1 spec allows, including the CRLF
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+// address block plus TLVs; we only read the addresses but must consume the rest
+const V2_MAX_PAYLOAD: usize = 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct ProxyHeader {
+    /// original (source, destination), or `None` for health checks
+    /// (`UNKNOWN` / `LOCAL`) where the real connection should be used
+    pub addresses: Option<(SocketAddr, SocketAddr)>,
+}
+
+fn invalid(why: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, why)
+}
+
+/// Reads a PROXY protocol v1 or v2 header from a nonblocking socket,
+/// resuming where it left off after `WouldBlock`. It never reads past the
+/// end of the header, so whatever follows stays in the socket for the TLS
+/// handshake or HTTP parser.
+pub struct ProxyHeaderReader {
+    buf: Vec<u8>,
+}
+
+impl ProxyHeaderReader {
+    pub fn new() -> ProxyHeaderReader {
+        ProxyHeaderReader { buf: Vec::new() }
+    }
+
+    // number of bytes that can be read without going past the header
+    fn remaining(&self) -> io::Result<usize> {
+        let buf = &self.buf;
+        if buf.len() < V1_PREFIX.len() {
+            return Ok(V1_PREFIX.len() - buf.len());
+        }
+        if buf.starts_with(V1_PREFIX) {
+            if buf.ends_with(b"\r\n") {
+                return Ok(0);
+            }
+            if buf.len() >= V1_MAX_LEN {
+                return Err(invalid("PROXY v1 header too long"));
+            }
+            // the line length is unknown, so go a byte at a time
+            return Ok(1);
+        }
+        let signature_len = buf.len().min(V2_SIGNATURE.len());
+        if buf[..signature_len] != V2_SIGNATURE[..signature_len] {
+            return Err(invalid("missing PROXY protocol header"));
+        }
+        if buf.len() < V2_HEADER_LEN {
+            return Ok(V2_HEADER_LEN - buf.len());
+        }
+        let payload = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        if payload > V2_MAX_PAYLOAD {
+            return Err(invalid("PROXY v2 header too long"));
+        }
+        Ok(V2_HEADER_LEN + payload - buf.len())
+    }
+
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> io::Result<ProxyHeader> {
+        loop {
+            let n = self.remaining()?;
+            if n == 0 {
+                return parse(&self.buf);
+            }
+            let start = self.buf.len();
+            self.buf.resize(start + n, 0);
+            match reader.read(&mut self.buf[start..]) {
+                Ok(0) => {
+                    self.buf.truncate(start);
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                Ok(read) => self.buf.truncate(start + read),
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+pub fn parse(header: &[u8]) -> io::Result<ProxyHeader> {
+    if header.starts_with(V1_PREFIX) {
+        parse_v1(header)
+    } else {
+        parse_v2(header)
+    }
+}
+
+fn parse_v1(header: &[u8]) -> io::Result<ProxyHeader> {
+    let Some(line) = header.strip_suffix(b"\r\n") else {
+        return Err(invalid("PROXY v1 header is not terminated"));
+    };
+    let Ok(line) = std::str::from_utf8(line) else {
+        return Err(invalid("PROXY v1 header is not ascii"));
+    };
+    let words: Vec<&str> = line.split(' ').collect();
+    match words.get(1) {
+        Some(&"UNKNOWN") => return Ok(ProxyHeader { addresses: None }),
+        Some(&"TCP4") | Some(&"TCP6") => {}
+        _ => return Err(invalid("unknown PROXY v1 protocol")),
+    }
+    if words.len() != 6 {
+        return Err(invalid("wrong number of fields in PROXY v1 header"));
+    }
+    let protocol = words[1];
+    let parse_ip = |s: &str| -> io::Result<IpAddr> {
+        let ip: IpAddr = s
+            .parse()
+            .map_err(|_| invalid("bad address in PROXY v1 header"))?;
+        if ip.is_ipv4() != (protocol == "TCP4") {
+            return Err(invalid("address family does not match PROXY v1 protocol"));
+        }
+        Ok(ip)
+    };
+    let parse_port = |s: &str| -> io::Result<u16> {
+        s.parse()
+            .map_err(|_| invalid("bad port in PROXY v1 header"))
+    };
+    let source = SocketAddr::new(parse_ip(words[2])?, parse_port(words[4])?);
+    let destination = SocketAddr::new(parse_ip(words[3])?, parse_port(words[5])?);
+    Ok(ProxyHeader {
+        addresses: Some((source, destination)),
+    })
+}
+
+fn parse_v2(header: &[u8]) -> io::Result<ProxyHeader> {
+    if header.len() < V2_HEADER_LEN || !header.starts_with(V2_SIGNATURE) {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0f;
+    let family = header[13] >> 4;
+    let payload = &header[V2_HEADER_LEN..];
+    if version != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match command {
+        // LOCAL: sent by the proxy itself, e.g. for health checks
+        0 => return Ok(ProxyHeader { addresses: None }),
+        1 => {}
+        _ => return Err(invalid("unknown PROXY v2 command")),
+    }
+    let addresses = match family {
+        // AF_INET
+        1 if payload.len() >= 12 => {
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    payload[at],
+                    payload[at + 1],
+                    payload[at + 2],
+                    payload[at + 3],
+                ))
+            };
+            let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+            Some((
+                SocketAddr::new(ip(0), port(8)),
+                SocketAddr::new(ip(4), port(10)),
+            ))
+        }
+        // AF_INET6
+        2 if payload.len() >= 36 => {
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&payload[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+            Some((
+                SocketAddr::new(ip(0), port(32)),
+                SocketAddr::new(ip(16), port(34)),
+            ))
+        }
+        1 | 2 => return Err(invalid("truncated PROXY v2 addresses")),
+        // AF_UNSPEC and AF_UNIX carry nothing we can use as a peer address
+        _ => None,
+    };
+    Ok(ProxyHeader { addresses })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_v1_tcp4() {
+        let header = parse(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n").unwrap();
+        let expected = Some((addr("192.0.2.1:56324"), addr("198.51.100.2:443")));
+        assert_eq!(header.addresses, expected);
+    }
+
+    #[test]
+    fn test_v1_tcp6() {
+        let header = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n").unwrap();
+        let expected = Some((addr("[2001:db8::1]:4000"), addr("[2001:db8::2]:443")));
+        assert_eq!(header.addresses, expected);
+    }
+
+    #[test]
+    fn test_v1_unknown() {
+        let header = parse(b"PROXY UNKNOWN\r\n").unwrap();
+        assert_eq!(header.addresses, None);
+    }
+
+    #[test]
+    fn test_v1_family_mismatch() {
+        assert!(parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").is_err());
+    }
+
+    #[test]
+    fn test_v2_inet() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 12]);
+        header.extend([192, 0, 2, 1, 198, 51, 100, 2]);
+        header.extend(56324u16.to_be_bytes());
+        header.extend(443u16.to_be_bytes());
+        let expected = Some((addr("192.0.2.1:56324"), addr("198.51.100.2:443")));
+        assert_eq!(parse(&header).unwrap().addresses, expected);
+    }
+
+    #[test]
+    fn test_v2_local() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20, 0x00, 0, 0]);
+        assert_eq!(parse(&header).unwrap().addresses, None);
+    }
+
+    #[test]
+    fn test_reader_stops_at_header() {
+        let mut input: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 1 2\r\nGET / HTTP/1.1\r\n";
+        let mut reader = ProxyHeaderReader::new();
+        assert!(reader.read_from(&mut input).unwrap().addresses.is_some());
+        assert_eq!(input, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn test_reader_rejects_plain_http() {
+        let mut input: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(ProxyHeaderReader::new().read_from(&mut input).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::request_id;
+use crate::types::{HttpResponse, StatusCode};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// More ranges than this, after merging, are answered with the whole body.
+const MAX_RANGES: usize = 16;
+
+/// What to make of a `Range` field for a body of a known length.
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// malformed or not in bytes: send the whole body
+    Ignore,
+    /// no range overlaps the body: 416
+    Unsatisfiable,
+    /// inclusive (first, last) byte positions, sorted and merged
+    Ranges(Vec<(u64, u64)>),
+}
+
+// one byte-range-spec or suffix-byte-range-spec, clamped to the body
+fn parse_one(spec: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let (first, last) = spec.trim().split_once('-').ok_or(())?;
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if first.is_empty() {
+        // the final `last` bytes
+        if !digits(last) {
+            return Err(());
+        }
+        let n: u64 = last.parse().map_err(|_| ())?;
+        return Ok((n > 0 && len > 0).then(|| (len.saturating_sub(n), len - 1)));
+    }
+    if !digits(first) || !(last.is_empty() || digits(last)) {
+        return Err(());
+    }
+    let first: u64 = first.parse().map_err(|_| ())?;
+    let last = match last {
+        "" => u64::MAX,
+        _ => last.parse().map_err(|_| ())?,
+    };
+    if last < first {
+        return Err(());
+    }
+    Ok((first < len).then(|| (first, last.min(len - 1))))
+}
+
+/// Parses a `Range` field value, e.g. `bytes=0-499, -500`.
+pub fn parse(value: &str, len: u64) -> RangeRequest {
+    let Some(specs) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Ignore;
+    };
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        match parse_one(spec, len) {
+            Ok(Some(range)) => ranges.push(range),
+            Ok(None) => {}
+            Err(()) => return RangeRequest::Ignore,
+        }
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    // overlapping or adjacent ranges become one, so nothing is sent twice
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    if merged.len() > MAX_RANGES {
+        return RangeRequest::Ignore;
+    }
+    RangeRequest::Ranges(merged)
+}
+
+/// The bytes of each of `ranges` of `body`.
+pub fn slice(body: &[u8], ranges: &[(u64, u64)]) -> Vec<Vec<u8>> {
+    ranges
+        .iter()
+        .map(|&(first, last)| body[first as usize..=last as usize].to_vec())
+        .collect()
+}
+
+/// The bytes of each of `ranges`, read from `file` without reading the
+/// rest of it.
+pub fn read<F: Read + Seek>(file: &mut F, ranges: &[(u64, u64)]) -> io::Result<Vec<Vec<u8>>> {
+    let mut parts = Vec::with_capacity(ranges.len());
+    for &(first, last) in ranges {
+        file.seek(SeekFrom::Start(first))?;
+        let mut part = vec![0; (last - first + 1) as usize];
+        file.read_exact(&mut part)?;
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+/// `206 Partial Content` from `parts`, the bytes of each of `ranges` of a
+/// body `len` bytes long.
+pub fn partial_response(
+    len: u64,
+    ranges: &[(u64, u64)],
+    mut parts: Vec<Vec<u8>>,
+    content_type: &str,
+) -> HttpResponse {
+    let content_range = |(first, last): (u64, u64)| format!("bytes {}-{}/{}", first, last, len);
+    if let ([range], [_]) = (ranges, &parts[..]) {
+        let part = parts.pop().unwrap_or_default();
+        let mut response = HttpResponse::new(StatusCode::PartialContent, Some(part));
+        response
+            .fields
+            .push(("Content-Range".to_string(), content_range(*range)));
+        response
+            .fields
+            .push(("Content-Type".to_string(), content_type.to_string()));
+        return response;
+    }
+    let boundary = request_id::generate();
+    let mut multipart = Vec::new();
+    for (range, part) in ranges.iter().zip(parts) {
+        multipart.extend(
+            format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                content_range(*range)
+            )
+            .into_bytes(),
+        );
+        multipart.extend(part);
+    }
+    multipart.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+    let mut response = HttpResponse::new(StatusCode::PartialContent, Some(multipart));
+    response.fields.push((
+        "Content-Type".to_string(),
+        format!("multipart/byteranges; boundary={}", boundary),
+    ));
+    response
+}
+
+/// `416 Range Not Satisfiable` for a body of `len` bytes.
+pub fn unsatisfiable_response(len: u64) -> HttpResponse {
+    let mut response = HttpResponse::new(StatusCode::RangeNotSatisfiable, None);
+    response
+        .fields
+        .push(("Content-Range".to_string(), format!("bytes */{}", len)));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        use RangeRequest::*;
+        assert_eq!(parse("bytes=0-499", 1000), Ranges(vec![(0, 499)]));
+        assert_eq!(parse("bytes=500-", 1000), Ranges(vec![(500, 999)]));
+        assert_eq!(parse("bytes=-200", 1000), Ranges(vec![(800, 999)]));
+        assert_eq!(parse("bytes=-2000", 1000), Ranges(vec![(0, 999)]));
+        assert_eq!(parse("bytes=900-5000", 1000), Ranges(vec![(900, 999)]));
+        assert_eq!(
+            parse("bytes=500-599, 0-99", 1000),
+            Ranges(vec![(0, 99), (500, 599)])
+        );
+        // overlapping and adjacent ranges merge
+        assert_eq!(
+            parse("bytes=0-99,50-149,150-199", 1000),
+            Ranges(vec![(0, 199)])
+        );
+        // unsatisfiable parts are dropped while others remain
+        assert_eq!(parse("bytes=0-9,2000-", 1000), Ranges(vec![(0, 9)]));
+        assert_eq!(parse("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), Unsatisfiable);
+        assert_eq!(parse("bytes=5-1", 1000), Ignore);
+        assert_eq!(parse("bytes=a-b", 1000), Ignore);
+        assert_eq!(parse("items=0-5", 1000), Ignore);
+        let many: Vec<String> = (0..20).map(|i| format!("{}-{}", i * 10, i * 10)).collect();
+        assert_eq!(parse(&format!("bytes={}", many.join(",")), 1000), Ignore);
+    }
+
+    #[test]
+    fn test_partial_response() {
+        let body = b"0123456789";
+        let single = partial_response(10, &[(2, 4)], slice(body, &[(2, 4)]), "text/plain");
+        assert_eq!(single.body.as_deref(), Some(&b"234"[..]));
+        assert!(
+            single
+                .fields
+                .contains(&("Content-Range".to_string(), "bytes 2-4/10".to_string()))
+        );
+
+        let ranges = [(0, 1), (8, 9)];
+        let mut file = io::Cursor::new(body);
+        let parts = read(&mut file, &ranges).unwrap();
+        assert_eq!(parts, slice(body, &ranges));
+        let multi = partial_response(10, &ranges, parts, "text/plain");
+        let text = String::from_utf8(multi.body.unwrap()).unwrap();
+        assert!(text.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n--"));
+        assert!(text.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n--"));
+        assert!(text.ends_with("--\r\n"));
+
+        // the file shrank since its length was looked up
+        assert!(read(&mut io::Cursor::new(&body[..5]), &[(3, 7)]).is_err());
+    }
+}
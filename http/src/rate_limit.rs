@@ -0,0 +1,295 @@
+use crate::deferred::{PendingResponse, Reply};
+use crate::server::HttpHandler;
+use crate::types::{HttpRequest, HttpResponse, StatusCode};
+use crate::{RateLimitConfig, RateLimitKey, RouteLimit};
+use log::warn;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub type KeyFn = Box<dyn Fn(&HttpRequest) -> Option<String>>;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RouteLimit, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.last = now;
+    }
+
+    /// Whether it would be full by `now`, without touching it, as that
+    /// would lose when it was last used.
+    fn full(&self, limit: &RouteLimit, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens + elapsed * limit.per_second >= limit.burst as f64
+    }
+}
+
+/// Token buckets per route and client. The table holds at most
+/// `max_clients` buckets; when it is full, buckets that have refilled
+/// completely are dropped first (they behave like new ones), then the
+/// least recently used.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    key_fn: Option<KeyFn>,
+    buckets: HashMap<(usize, String), Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            key_fn: None,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Used for routes keyed by `RateLimitKey::Custom`, e.g. to limit an
+    /// authenticated user across addresses. Requests it returns `None` for
+    /// are keyed by client address.
+    pub fn set_key_fn(&mut self, key_fn: KeyFn) {
+        self.key_fn = Some(key_fn);
+    }
+
+    fn key(&self, route: &RouteLimit, request: &HttpRequest) -> String {
+        if route.key == RateLimitKey::Custom
+            && let Some(key) = self.key_fn.as_ref().and_then(|f| f(request))
+        {
+            return key;
+        }
+        request.client_addr.to_string()
+    }
+
+    fn make_room(&mut self, now: Instant) {
+        let routes = &self.config.routes;
+        self.buckets
+            .retain(|(route, _), bucket| !bucket.full(&routes[*route], now));
+        if self.buckets.len() < self.config.max_clients {
+            return;
+        }
+        let oldest = self
+            .buckets
+            .iter()
+            .min_by_key(|(_, b)| b.last)
+            .map(|(k, _)| k.clone());
+        if let Some(k) = oldest {
+            self.buckets.remove(&k);
+        }
+    }
+
+    /// Takes a token for `request`, or says how long until one is available.
+    pub fn check(&mut self, request: &HttpRequest) -> Result<(), Duration> {
+        self.check_at(request, Instant::now())
+    }
+
+    fn check_at(&mut self, request: &HttpRequest, now: Instant) -> Result<(), Duration> {
+        let Some(index) = self
+            .config
+            .routes
+            .iter()
+            .position(|r| request.path.starts_with(&r.path))
+        else {
+            return Ok(());
+        };
+        let key = (index, self.key(&self.config.routes[index], request));
+        if !self.buckets.contains_key(&key) && self.buckets.len() >= self.config.max_clients {
+            self.make_room(now);
+        }
+
+        let limit = &self.config.routes[index];
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            last: now,
+        });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / limit.per_second,
+        ))
+    }
+}
+
+/// Wraps a handler, answering `429 Too Many Requests` instead of calling it
+/// when a client is over its limit.
+pub struct RateLimited<H: HttpHandler> {
+    handler: H,
+    limiter: RefCell<RateLimiter>,
+}
+
+impl<H: HttpHandler> RateLimited<H> {
+    pub fn new(handler: H, limiter: RateLimiter) -> RateLimited<H> {
+        RateLimited {
+            handler,
+            limiter: RefCell::new(limiter),
+        }
+    }
+
+    // the 429 to answer with, if the client is over its limit
+    fn limited(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        let retry_after = self.limiter.borrow_mut().check(request).err()?;
+        warn!("rate limited {} on {}", request.client_addr, request.path);
+        let mut response = HttpResponse::new(StatusCode::TooManyRequests, None);
+        // whole seconds, rounded up so clients do not retry too early
+        let seconds = retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64;
+        response
+            .fields
+            .push(("Retry-After".to_string(), seconds.max(1).to_string()));
+        Some(response)
+    }
+}
+
+impl<H: HttpHandler> HttpHandler for RateLimited<H> {
+    fn handle(&self, request: HttpRequest) -> HttpResponse {
+        match self.limited(&request) {
+            Some(response) => response,
+            None => self.handler.handle(request),
+        }
+    }
+
+    fn reply(&self, request: HttpRequest, pending: PendingResponse) -> Reply {
+        match self.limited(&request) {
+            Some(response) => {
+                pending.forget();
+                Reply::Now(response)
+            }
+            None => self.handler.reply(request, pending),
+        }
+    }
+
+    fn route(&self, request: &HttpRequest) -> &'static str {
+        self.handler.route(request)
+    }
+
+    fn finish(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        self.handler.finish(request, response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Method;
+
+    fn request(client: &str, path: &str) -> HttpRequest {
+        HttpRequest::new(Method::Get, path).with_peer(format!("{}:1234", client).parse().unwrap())
+    }
+
+    fn route(path: &str, burst: u32, per_second: f64) -> RouteLimit {
+        RouteLimit {
+            path: path.to_string(),
+            burst,
+            per_second,
+            key: RateLimitKey::ClientAddr,
+        }
+    }
+
+    fn limiter(routes: Vec<RouteLimit>, max_clients: usize) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            routes,
+            max_clients,
+        })
+    }
+
+    #[test]
+    fn test_refill_and_burst() {
+        let mut limiter = limiter(vec![route("/", 2, 1.0)], 100);
+        let request = request("192.0.2.1", "/");
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        assert_eq!(limiter.check_at(&request, t0), Ok(()));
+        assert_eq!(limiter.check_at(&request, t0), Ok(()));
+        assert_eq!(limiter.check_at(&request, t0), Err(Duration::from_secs(1)));
+        assert_eq!(
+            limiter.check_at(&request, at(500)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(limiter.check_at(&request, at(1000)), Ok(()));
+        // a long pause refills no more than the burst
+        assert_eq!(limiter.check_at(&request, at(60_000)), Ok(()));
+        assert_eq!(limiter.check_at(&request, at(60_000)), Ok(()));
+        assert!(limiter.check_at(&request, at(60_000)).is_err());
+    }
+
+    #[test]
+    fn test_routes() {
+        let mut limiter = limiter(
+            vec![
+                route("/api/create-account", 1, 0.01),
+                route("/api", 2, 0.01),
+            ],
+            100,
+        );
+        let now = Instant::now();
+        let check = |limiter: &mut RateLimiter, path: &str| {
+            limiter.check_at(&request("192.0.2.1", path), now).is_ok()
+        };
+        // the first route whose prefix matches applies
+        assert!(check(&mut limiter, "/api/create-account"));
+        assert!(!check(&mut limiter, "/api/create-account"));
+        assert!(check(&mut limiter, "/api/get-notes"));
+        assert!(check(&mut limiter, "/api/who-am-i"));
+        assert!(!check(&mut limiter, "/api/get-notes"));
+        // no route, no limit
+        for _ in 0..10 {
+            assert!(check(&mut limiter, "/index.html"));
+        }
+        // clients have buckets of their own
+        assert!(
+            limiter
+                .check_at(&request("192.0.2.2", "/api/get-notes"), now)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_make_room() {
+        let mut limiter = limiter(vec![route("/", 1, 1.0)], 2);
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        let (a, b, c) = (
+            request("192.0.2.1", "/"),
+            request("192.0.2.2", "/"),
+            request("192.0.2.3", "/"),
+        );
+        assert!(limiter.check_at(&a, t0).is_ok());
+        assert!(limiter.check_at(&b, at(900)).is_ok());
+        // a has refilled and goes first; b has not and stays limited
+        assert!(limiter.check_at(&c, at(1500)).is_ok());
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(limiter.check_at(&b, at(1550)).is_err());
+
+        // with nothing refilled, the least recently used bucket goes: c,
+        // then b, while a keeps its empty bucket
+        assert!(limiter.check_at(&a, at(1600)).is_ok());
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(limiter.check_at(&c, at(1700)).is_ok());
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(limiter.check_at(&a, at(1700)).is_err());
+    }
+
+    struct Ok200;
+
+    impl HttpHandler for Ok200 {
+        fn handle(&self, _request: HttpRequest) -> HttpResponse {
+            HttpResponse::new(StatusCode::OK, None)
+        }
+    }
+
+    #[test]
+    fn test_too_many_requests() {
+        let handler = RateLimited::new(Ok200, limiter(vec![route("/", 1, 1.0 / 60.0)], 100));
+        let ok = handler.handle(request("192.0.2.1", "/"));
+        assert_eq!(ok.status_code, StatusCode::OK);
+        let limited = handler.handle(request("192.0.2.1", "/"));
+        assert_eq!(limited.status_code, StatusCode::TooManyRequests);
+        let retry_after: u64 = limited.field("Retry-After").unwrap().parse().unwrap();
+        assert!((59..=60).contains(&retry_after));
+    }
+}
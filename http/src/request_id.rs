@@ -0,0 +1,101 @@
+use crate::types::HttpRequest;
+use std::cell::RefCell;
+use std::process;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_LEN: usize = 128;
+
+static PREFIX: OnceLock<u64> = OnceLock::new();
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// ids end up in log lines, so only allow what cannot break them up
+fn valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+/// A new id, unique within this process and unlikely to repeat across
+/// restarts: a per-process prefix followed by a counter.
+pub fn generate() -> String {
+    let prefix = PREFIX.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        started ^ ((process::id() as u64) << 32)
+    });
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}-{:08x}", prefix, n)
+}
+
+/// The client's (or a proxy's) `X-Request-Id` if it is usable, or a new id.
+pub fn for_request(request: &HttpRequest) -> String {
+    match request.field("X-Request-Id") {
+        Some(id) if valid(id) => id.to_string(),
+        _ => generate(),
+    }
+}
+
+/// The id of the request being handled on this thread, for log lines.
+pub fn current() -> Option<String> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Makes `id` the current request id until dropped.
+pub struct Scope {
+    previous: Option<String>,
+}
+
+pub fn enter(id: &str) -> Scope {
+    let previous = CURRENT.with(|c| c.replace(Some(id.to_string())));
+    Scope { previous }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|c| *c.borrow_mut() = self.previous.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid() {
+        assert!(valid("f47ac10b-58cc-4372-a567-0e02b2c3d479"));
+        assert!(!valid(""));
+        assert!(!valid("abc def"));
+        assert!(!valid("abc\r\nX-Injected: 1"));
+        assert!(!valid(&"a".repeat(MAX_LEN + 1)));
+    }
+
+    #[test]
+    fn test_generate() {
+        let (a, b) = (generate(), generate());
+        assert_ne!(a, b);
+        assert!(valid(&a));
+    }
+
+    #[test]
+    fn test_scope() {
+        assert_eq!(current(), None);
+        {
+            let _outer = enter("a");
+            {
+                let _inner = enter("b");
+                assert_eq!(current().as_deref(), Some("b"));
+            }
+            assert_eq!(current().as_deref(), Some("a"));
+        }
+        assert_eq!(current(), None);
+    }
+}
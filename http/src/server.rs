@@ -1,23 +1,118 @@
 use crate::ServerConfig;
+use crate::access_log::{AccessLog, AccessLogEntry};
+use crate::compression;
+use crate::deferred::{Completions, PendingResponse, Reply};
+use crate::forwarded;
+use crate::limits::{Admission, ConnectionLimiter};
+use crate::metrics::{METRICS, RequestRecord};
 use crate::parser::*;
-use crate::socket::Listener;
-use crate::types::{HttpRequest, HttpResponse, Responder};
+use crate::request_id;
+use crate::socket::{Listener, Stream};
+use crate::tls::TlsError;
+use crate::types::{HttpRequest, HttpResponse, Responder, StatusCode};
 use log::{info, warn};
+use nix::errno::Errno;
 use nix::poll::PollTimeout;
 use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags};
-use std::io::{BufReader, Error};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
 use std::os::fd::AsFd;
-use std::time::Duration;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 const DATA: u64 = 17;
+// parked streams are registered with their pending response key and this bit
+const PARKED: u64 = 1 << 63;
+const ACCEPT_PAUSE: Duration = Duration::from_secs(1);
+const TICK_MILLIS: u16 = 500;
 
 type HttpHandlerT<'a> = Box<dyn HttpHandler + 'a>;
 pub trait HttpHandler {
     fn handle(&self, request: HttpRequest) -> HttpResponse;
+
+    /// Like `handle`, but the answer may come later: keep `pending`, return
+    /// `Reply::Later` and complete it from anywhere, e.g. another thread or
+    /// a timer. The connection waits without holding up the server.
+    fn reply(&self, request: HttpRequest, pending: PendingResponse) -> Reply {
+        pending.forget();
+        Reply::Now(self.handle(request))
+    }
+
+    /// Groups requests in metrics, e.g. `/api/add-note`. Return one of a
+    /// few fixed names rather than anything taken from the raw path.
+    fn route(&self, _request: &HttpRequest) -> &'static str {
+        "other"
+    }
+
+    /// A last look at every response before it is sent, including those
+    /// the server makes itself (`500` for a panic, `503` when overloaded),
+    /// e.g. to give error responses a page. `request` has no body.
+    fn finish(&self, _request: &HttpRequest, _response: &mut HttpResponse) {}
+}
+
+#[derive(Debug)]
+pub enum ServerError {
+    Bind(io::Error),
+    Tls(TlsError),
+    AccessLog(String, io::Error),
+}
+impl Error for ServerError {}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ServerError::*;
+        match self {
+            Bind(e) => write!(f, "could not bind listener: {}", e),
+            Tls(e) => write!(f, "could not enable tls: {}", e),
+            AccessLog(path, e) => write!(f, "could not open access log `{}`: {}", path, e),
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Bind(e)
+    }
+}
+
+impl From<TlsError> for ServerError {
+    fn from(e: TlsError) -> ServerError {
+        ServerError::Tls(e)
+    }
+}
+
+fn out_of_fds(e: &io::Error) -> bool {
+    let errno = e.raw_os_error().map(Errno::from_raw);
+    matches!(errno, Some(Errno::EMFILE) | Some(Errno::ENFILE))
+}
+
+fn overloaded_response() -> HttpResponse {
+    let mut response = HttpResponse::new(StatusCode::ServiceUnavailable, None);
+    response.fields.push((
+        "Retry-After".to_string(),
+        ACCEPT_PAUSE.as_secs().to_string(),
+    ));
+    response
+}
+
+// a request on its way to being answered
+struct Exchange {
+    stream: Stream,
+    /// without its body
+    request: HttpRequest,
+    record: RequestRecord,
+    log_entry: Option<AccessLogEntry>,
 }
 
 pub struct HttpServer<'a> {
+    config: &'a ServerConfig,
     listener: Listener,
+    access_log: Option<AccessLog>,
     default_handler: HttpHandlerT<'a>,
 }
 
@@ -25,20 +120,157 @@ impl<'a> HttpServer<'a> {
     pub fn new(
         config: &'a ServerConfig,
         default_handler: HttpHandlerT<'a>,
-    ) -> Result<HttpServer<'a>, Error> {
-        let mut listener = match Listener::bind(&config.address) {
-            Ok(l) => l,
-            Err(e) => return Err(e),
-        };
+    ) -> Result<HttpServer<'a>, ServerError> {
+        let mut listener = Listener::bind(&config.address)?;
         if let Some(tls) = &config.tls {
-            listener.enable_tls(&tls.cert, &tls.key).unwrap();
+            listener.enable_tls(tls)?;
         }
+        if config.proxy_protocol {
+            listener.expect_proxy_protocol();
+        }
+        let access_log = match &config.access_log {
+            Some(c) => Some(
+                AccessLog::open(&c.path, c.format.clone())
+                    .map_err(|e| ServerError::AccessLog(c.path.clone(), e))?,
+            ),
+            None => None,
+        };
         Ok(HttpServer {
-            listener: listener,
-            default_handler: default_handler,
+            config,
+            listener,
+            access_log,
+            default_handler,
         })
     }
 
+    fn accept(
+        &self,
+        epoll: &Epoll,
+        limiter: &ConnectionLimiter,
+    ) -> io::Result<Option<AsyncHttpParser>> {
+        let (mut stream, addr) = self.listener.accept()?;
+        // a proxy's connections are counted per client once it says which
+        let proxied = self.config.proxy_protocol
+            || self
+                .config
+                .trusted_proxies
+                .iter()
+                .any(|cidr| cidr.contains(&addr.ip()));
+        let slot = if proxied {
+            limiter.admit_proxied(addr.ip())
+        } else {
+            limiter.admit(addr.ip())
+        };
+        match slot.admission() {
+            Admission::Accept => info!("Connection established with {}", addr),
+            Admission::Reject => warn!("too many connections, rejecting {}", addr),
+            Admission::Close => {
+                warn!("too many connections, closing {}", addr);
+                return Ok(None);
+            }
+        }
+        stream.hold_slot(slot);
+        if let Err(e) = epoll.add(stream.as_fd(), EpollEvent::new(EpollFlags::EPOLLIN, DATA)) {
+            warn!("failed to add TCP stream to Epoll: {}", e);
+            return Ok(None);
+        }
+        let mut parser = AsyncHttpParser::new(BufReader::new(stream));
+        parser.set_timeout(Duration::from_secs(2));
+        Ok(Some(parser))
+    }
+
+    // a panicking handler costs its own request a 500, not the whole server
+    fn handle(&self, request: HttpRequest, pending: PendingResponse) -> Reply {
+        let summary = request.to_string();
+        let handler = &self.default_handler;
+        match panic::catch_unwind(AssertUnwindSafe(|| handler.reply(request, pending))) {
+            Ok(reply) => reply,
+            Err(payload) => {
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(s) => s,
+                    None => payload
+                        .downcast_ref::<String>()
+                        .map_or("unknown cause", |s| s.as_str()),
+                };
+                warn!("handler panicked on {}: {}", summary, message);
+                Reply::Now(HttpResponse::new(StatusCode::InternalError, None))
+            }
+        }
+    }
+
+    // starts writing `response`, giving back the responder if that takes
+    // more than one go
+    fn start_response(
+        &self,
+        epoll: &Epoll,
+        exchange: Exchange,
+        mut response: HttpResponse,
+    ) -> Option<Responder> {
+        let Exchange {
+            stream,
+            request,
+            mut record,
+            mut log_entry,
+        } = exchange;
+        let _id_scope = request_id::enter(&request.id);
+        let handler = &self.default_handler;
+        if panic::catch_unwind(AssertUnwindSafe(|| handler.finish(&request, &mut response)))
+            .is_err()
+        {
+            warn!("handler panicked finishing {}", request);
+        }
+        if let Some(config) = &self.config.compression {
+            compression::compress(&mut response, request.field("Accept-Encoding"), config);
+        }
+        response
+            .fields
+            .push(("X-Request-Id".to_string(), request.id.clone()));
+        record.set_status(&response.status_code);
+        if let Some(entry) = &mut log_entry {
+            entry.set_response(&response);
+        }
+        let mut responder = Responder::from_http_response(response, stream);
+        responder.set_record(record);
+        if let Some(entry) = log_entry {
+            responder.set_log_entry(entry);
+        }
+        match responder.respond() {
+            Future::Done(()) => self.finish_response(&mut responder),
+            Future::Wait => {
+                if let Err(e) = epoll.modify(
+                    responder.as_fd(),
+                    &mut EpollEvent::new(EpollFlags::EPOLLOUT, DATA),
+                ) {
+                    warn!("failed to modify fd: {}", e);
+                }
+                info!("could not send full response... adding responder to queue");
+                return Some(responder);
+            }
+            Future::Fail(s) => {
+                warn!("response failed: {}", s);
+                self.finish_response(&mut responder);
+            }
+        }
+        if let Err(e) = epoll.delete(responder.as_fd()) {
+            warn!("failed to delete fd: {}", e);
+        }
+        None
+    }
+
+    fn finish_response(&self, responder: &mut Responder) {
+        if let Some(record) = responder.take_record() {
+            record.finish();
+        }
+        if let (Some(log), Some(entry)) = (&self.access_log, responder.take_log_entry()) {
+            log.record(&entry);
+        }
+    }
+
+    /// Where the server listens, e.g. to learn the port picked for `:0`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
     // listens async
     pub fn listen(&self) {
         let epoll = match Epoll::new(EpollCreateFlags::empty()) {
@@ -50,34 +282,85 @@ impl<'a> HttpServer<'a> {
         };
 
         if let Err(e) = epoll.add(
-            &self.listener.as_fd(),
+            self.listener.as_fd(),
             EpollEvent::new(EpollFlags::EPOLLIN, DATA),
         ) {
             warn!("Could not wait for TCP Listener: {}", e);
             return;
         }
 
+        let mut completions = match Completions::new() {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Could not make eventfd for pending responses: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = epoll.add(
+            completions.as_fd(),
+            EpollEvent::new(EpollFlags::EPOLLIN, DATA),
+        ) {
+            warn!("Could not wait for pending responses: {}", e);
+            return;
+        }
+
+        let limiter = ConnectionLimiter::new(self.config.limits);
+        let mut listening = true;
+        // set after running out of file descriptors: (until, open connections then)
+        let mut accept_paused: Option<(Instant, usize)> = None;
         let mut active_parsers: Vec<AsyncHttpParser> = Vec::new();
         let mut active_responders: Vec<Responder> = Vec::new();
+        // connections whose handler will answer later, by pending response key,
+        // with the flag that tells the handler when the client is gone
+        let mut parked: HashMap<u64, (Exchange, Arc<AtomicBool>)> = HashMap::new();
+        // keys of parked connections that saw a hangup in the last wait
+        let mut hung_up: Vec<u64> = Vec::new();
         loop {
-            match self.listener.accept() {
-                Ok((stream, addr)) => {
-                    info!("Connection established with {}", addr);
-                    if let Err(e) =
-                        epoll.add(&stream.as_fd(), EpollEvent::new(EpollFlags::EPOLLIN, DATA))
-                    {
-                        warn!("failed to add TCP stream to Epoll: {}", e);
-                        continue;
-                    }
-                    let buf_reader = BufReader::new(stream);
-                    let mut parser = AsyncHttpParser::new(buf_reader);
-                    parser.set_timeout(Duration::from_secs(2));
-                    active_parsers.push(parser);
+            for key in hung_up.drain(..) {
+                let Some((exchange, cancelled)) = parked.remove(&key) else {
+                    continue;
+                };
+                let _id_scope = request_id::enter(&exchange.request.id);
+                info!(
+                    "client {} hung up while waiting",
+                    exchange.request.client_addr
+                );
+                cancelled.store(true, Ordering::Relaxed);
+                if let Err(e) = epoll.delete(exchange.stream.as_fd()) {
+                    warn!("failed to delete fd: {}", e);
                 }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                    } else {
-                        warn!("accept error: {}", e);
+            }
+            for (key, response) in completions.take() {
+                // unknown keys belong to handlers that answered right away,
+                // or to clients that hung up
+                let Some((exchange, _)) = parked.remove(&key) else {
+                    continue;
+                };
+                if let Err(e) = epoll.modify(
+                    exchange.stream.as_fd(),
+                    &mut EpollEvent::new(EpollFlags::EPOLLIN, DATA),
+                ) {
+                    warn!("failed to add TCP stream to Epoll: {}", e);
+                    continue;
+                }
+                if let Some(r) = self.start_response(&epoll, exchange, response) {
+                    active_responders.push(r);
+                }
+            }
+            if listening {
+                match self.accept(&epoll, &limiter) {
+                    Ok(Some(parser)) => active_parsers.push(parser),
+                    Ok(None) => {}
+                    Err(e) if out_of_fds(&e) => {
+                        // the listener stays readable, so retrying now would spin
+                        warn!("accept error: {}, pausing until a connection closes", e);
+                        accept_paused = Some((Instant::now() + ACCEPT_PAUSE, limiter.open()));
+                    }
+                    Err(e) => {
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                        } else {
+                            warn!("accept error: {}", e);
+                        }
                     }
                 }
             }
@@ -85,32 +368,60 @@ impl<'a> HttpServer<'a> {
             while i < active_parsers.len() {
                 let parser = &mut active_parsers[i];
                 match parser.parse() {
-                    Future::Done(http_request) => {
+                    Future::Done(mut http_request) => {
+                        // log lines up to the end of this arm carry the id
+                        let _id_scope = request_id::enter(&http_request.id);
+                        forwarded::apply(&mut http_request, &self.config.trusted_proxies);
                         info!("{}", http_request);
-                        let parser = active_parsers.swap_remove(i);
-                        let http_response = self.default_handler.handle(http_request);
-                        let mut responder =
-                            Responder::from_http_response(http_response, parser.into_stream());
-                        if match responder.respond() {
-                            Future::Done(()) => false,
-                            Future::Wait => true,
-                            Future::Fail(s) => {
-                                warn!("response failed: {}", s);
-                                false
+                        let route = self.default_handler.route(&http_request);
+                        let record = RequestRecord::new(&http_request, route);
+                        let log_entry = self
+                            .access_log
+                            .as_ref()
+                            .map(|_| AccessLogEntry::new(&http_request));
+                        // kept for `finish`, without cloning the body
+                        let body = http_request.body.take();
+                        let head = http_request.clone();
+                        http_request.body = body;
+                        let mut stream = active_parsers.swap_remove(i).into_stream();
+                        stream.reassign_slot(http_request.client_addr);
+                        if stream.admission() == &Admission::Close {
+                            warn!("too many connections, closing {}", http_request.client_addr);
+                            continue;
+                        }
+                        let pending = completions.pending();
+                        let key = pending.key();
+                        let cancelled = pending.cancel_flag();
+                        let reply = match stream.admission() {
+                            Admission::Reject => {
+                                pending.forget();
+                                Reply::Now(overloaded_response())
                             }
-                        } {
-                            if let Err(e) = epoll.modify(
-                                responder.as_fd(),
-                                &mut EpollEvent::new(EpollFlags::EPOLLOUT, DATA),
-                            ) {
-                                warn!("failed to modify fd: {}", e);
+                            _ => self.handle(http_request, pending),
+                        };
+                        let exchange = Exchange {
+                            stream,
+                            request: head,
+                            record,
+                            log_entry,
+                        };
+                        match reply {
+                            Reply::Now(response) => {
+                                if let Some(r) = self.start_response(&epoll, exchange, response) {
+                                    active_responders.push(r);
+                                }
                             }
-                            info!("could not send full response... adding responder to queue");
-                            active_responders.push(responder);
-                            info!("queue of {} responders", active_responders.len());
-                        } else {
-                            if let Err(e) = epoll.delete(responder.as_fd()) {
-                                warn!("failed to delete fd: {}", e);
+                            Reply::Later => {
+                                // nothing to read or write until the handler
+                                // answers, but a hangup should still be noticed
+                                if let Err(e) = epoll.modify(
+                                    exchange.stream.as_fd(),
+                                    &mut EpollEvent::new(EpollFlags::EPOLLRDHUP, PARKED | key),
+                                ) {
+                                    warn!("failed to park TCP stream in Epoll: {}", e);
+                                    continue;
+                                }
+                                parked.insert(key, (exchange, cancelled));
                             }
                         }
                     }
@@ -128,6 +439,7 @@ impl<'a> HttpServer<'a> {
                 let responder = &mut active_responders[i];
                 match responder.respond() {
                     Future::Done(()) => {
+                        self.finish_response(responder);
                         if let Err(e) = epoll.delete(responder.as_fd()) {
                             warn!("failed to delete fd: {}", e);
                         }
@@ -135,6 +447,7 @@ impl<'a> HttpServer<'a> {
                     }
                     Future::Fail(e) => {
                         warn!("Failed to respond: {}", e);
+                        self.finish_response(responder);
                         if let Err(e) = epoll.delete(responder.as_fd()) {
                             warn!("failed to delete fd: {}", e);
                         }
@@ -146,10 +459,120 @@ impl<'a> HttpServer<'a> {
                 };
             }
 
-            let mut events = [EpollEvent::empty()];
-            if let Err(e) = epoll.wait(&mut events, PollTimeout::NONE) {
-                warn!("failed to wait for events: {}", e);
+            if let Some((until, open)) = accept_paused
+                && (Instant::now() >= until || limiter.open() < open)
+            {
+                accept_paused = None;
+            }
+            let accept = accept_paused.is_none() && !limiter.paused();
+            if accept != listening {
+                let result = if accept {
+                    epoll.add(
+                        self.listener.as_fd(),
+                        EpollEvent::new(EpollFlags::EPOLLIN, DATA),
+                    )
+                } else {
+                    epoll.delete(self.listener.as_fd())
+                };
+                match result {
+                    Ok(()) => listening = accept,
+                    Err(e) => warn!("failed to update listener in Epoll: {}", e),
+                }
+            }
+
+            METRICS.set_connections(
+                &self.config.address,
+                active_parsers.len(),
+                active_responders.len(),
+                limiter.open(),
+            );
+
+            // wake up now and then to time out idle connections and resume
+            // accepting, as neither comes with an event
+            let timeout = if active_parsers.is_empty() && accept_paused.is_none() {
+                PollTimeout::NONE
+            } else {
+                PollTimeout::from(TICK_MILLIS)
+            };
+            let mut events = [EpollEvent::empty(); 64];
+            match epoll.wait(&mut events, timeout) {
+                Ok(n) => hung_up.extend(
+                    events[..n]
+                        .iter()
+                        .filter(|event| event.data() & PARKED != 0)
+                        .map(|event| event.data() & !PARKED),
+                ),
+                Err(e) => warn!("failed to wait for events: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConnectionLimits, OverloadPolicy};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::thread;
+
+    struct Panicky;
+
+    impl HttpHandler for Panicky {
+        fn handle(&self, request: HttpRequest) -> HttpResponse {
+            if request.path == "/panic" {
+                panic!("handler bug");
             }
+            HttpResponse::new(StatusCode::OK, Some(b"ok".to_vec()))
         }
     }
+
+    fn status(stream: &mut TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.split(' ').nth(1).unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn test_panic_is_500() {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let config = ServerConfig {
+                address: "127.0.0.1:0".to_string(),
+                tls: None,
+                proxy_protocol: false,
+                trusted_proxies: Vec::new(),
+                limits: ConnectionLimits {
+                    max_connections: 16,
+                    max_per_ip: 0,
+                    policy: OverloadPolicy::StopAccepting,
+                },
+                access_log: None,
+                compression: None,
+            };
+            let server = HttpServer::new(&config, Box::new(Panicky)).unwrap();
+            sender.send(server.local_addr().unwrap()).unwrap();
+            server.listen();
+        });
+        let addr = receiver.recv().unwrap();
+
+        // a connection that is in the middle of its request meanwhile
+        let mut waiting = TcpStream::connect(addr).unwrap();
+        waiting.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+        let mut panicking = TcpStream::connect(addr).unwrap();
+        panicking
+            .write_all(b"GET /panic HTTP/1.1\r\nHost: test\r\n\r\n")
+            .unwrap();
+        assert_eq!(status(&mut panicking), "500");
+
+        waiting.write_all(b"Host: test\r\n\r\n").unwrap();
+        assert_eq!(status(&mut waiting), "200");
+        let mut later = TcpStream::connect(addr).unwrap();
+        later
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+            .unwrap();
+        assert_eq!(status(&mut later), "200");
+    }
 }
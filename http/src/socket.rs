@@ -1,37 +1,31 @@
-use log::warn;
-use rustls::pki_types::pem::PemObject;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use crate::TlsConfig;
+use crate::limits::{Admission, ConnectionSlot};
+use crate::metrics::METRICS;
+use crate::proxy_protocol::ProxyHeaderReader;
+use crate::tls::{self, TlsError, make_tls_config};
+use crate::types::{ConnectionInfo, TlsInfo};
+use log::{info, warn};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
 use std::os::fd::{AsFd, BorrowedFd};
 use std::sync::Arc;
 
 pub struct Listener {
     tcp: TcpListener,
     tls_config: Option<Arc<rustls::ServerConfig>>,
+    proxy_protocol: bool,
 }
 
 pub struct Stream {
     tcp: TcpStream,
     conn: Option<rustls::ServerConnection>,
+    proxy_header: Option<ProxyHeaderReader>,
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    slot: Option<ConnectionSlot>,
     //tls: rustls::Stream<'static, rustls::ServerConnection, TcpStream>,
 }
 
-fn make_tls_config(
-    cert_file: &str,
-    private_key_file: &str,
-) -> Result<rustls::ServerConfig, rustls::Error> {
-    let certs = CertificateDer::pem_file_iter(cert_file)
-        .unwrap()
-        .map(|cert| cert.unwrap())
-        .collect();
-    let private_key = PrivateKeyDer::from_pem_file(private_key_file).unwrap();
-    let config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, private_key)?;
-    Ok(config)
-}
-
 type Fd<'a> = BorrowedFd<'a>;
 impl Listener {
     pub fn bind(addr: &str) -> std::io::Result<Listener> {
@@ -42,36 +36,114 @@ impl Listener {
         Ok(Listener {
             tcp: listener,
             tls_config: None,
+            proxy_protocol: false,
         })
     }
-    pub fn enable_tls(&mut self, cert_fp: &str, key_fp: &str) -> Result<(), rustls::Error> {
-        if let Some(_) = self.tls_config {
+
+    /// Connections must start with a PROXY protocol header, whose source
+    /// address then replaces the peer address of the load balancer.
+    pub fn expect_proxy_protocol(&mut self) {
+        self.proxy_protocol = true;
+    }
+    pub fn enable_tls(&mut self, tls: &TlsConfig) -> Result<(), TlsError> {
+        if self.tls_config.is_some() {
             panic!("tls already enabled");
         }
-        let config = make_tls_config(cert_fp, key_fp)?;
+        let config = make_tls_config(tls)?;
         self.tls_config = Some(Arc::new(config));
         Ok(())
     }
 
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
     pub fn accept(&self) -> std::io::Result<(Stream, SocketAddr)> {
         let (s, addr) = self.tcp.accept()?;
         if let Err(e) = s.set_nonblocking(true) {
             warn!("error setting stream to nonblocking: {}", e);
         }
-        if let Some(config) = &self.tls_config {
-            let Ok(conn) = rustls::ServerConnection::new(config.clone()) else {
-                panic!("could not make tls connection")
-            };
-            Ok((
-                Stream {
-                    tcp: s,
-                    conn: Some(conn),
-                },
-                addr,
-            ))
+        let local_addr = s.local_addr()?;
+        let conn = if let Some(config) = &self.tls_config {
+            // dropping `s` closes the connection
+            match rustls::ServerConnection::new(config.clone()) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    return Err(io::Error::other(format!(
+                        "could not make tls connection with {}: {}",
+                        addr, e
+                    )));
+                }
+            }
         } else {
             warn!("insecure connection");
-            Ok((Stream { tcp: s, conn: None }, addr))
+            None
+        };
+        Ok((
+            Stream {
+                tcp: s,
+                conn,
+                proxy_header: self.proxy_protocol.then(ProxyHeaderReader::new),
+                peer_addr: addr,
+                local_addr,
+                slot: None,
+            },
+            addr,
+        ))
+    }
+}
+
+impl Stream {
+    /// The slot is released when the stream is dropped.
+    pub(crate) fn hold_slot(&mut self, slot: ConnectionSlot) {
+        self.slot = Some(slot);
+    }
+
+    /// Counts the connection against the client it turned out to be for.
+    pub(crate) fn reassign_slot(&mut self, ip: IpAddr) {
+        if let Some(slot) = &mut self.slot {
+            slot.reassign(ip);
+        }
+    }
+
+    pub(crate) fn admission(&self) -> &Admission {
+        self.slot
+            .as_ref()
+            .map_or(&Admission::Accept, |slot| slot.admission())
+    }
+
+    // the header comes before anything else, including the tls handshake
+    fn read_proxy_header(&mut self) -> io::Result<()> {
+        let Some(reader) = &mut self.proxy_header else {
+            return Ok(());
+        };
+        let header = reader.read_from(&mut self.tcp)?;
+        if let Some((source, destination)) = header.addresses {
+            info!("{} is proxying for {}", self.peer_addr, source);
+            self.reassign_slot(source.ip());
+            self.peer_addr = source;
+            self.local_addr = destination;
+        }
+        self.proxy_header = None;
+        Ok(())
+    }
+
+    /// TLS details are only complete once the handshake is done.
+    pub fn connection_info(&self) -> ConnectionInfo {
+        let tls = self.conn.as_ref().map(|conn| TlsInfo {
+            sni: conn.server_name().map(|s| s.to_string()),
+            alpn: conn
+                .alpn_protocol()
+                .map(|p| String::from_utf8_lossy(p).into_owned()),
+            client_cert: conn
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(tls::client_certificate),
+        });
+        ConnectionInfo {
+            peer_addr: self.peer_addr,
+            local_addr: self.local_addr,
+            tls,
         }
     }
 }
@@ -90,23 +162,39 @@ impl AsFd for Stream {
 
 impl Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if let Some(conn) = &mut self.conn {
+        self.read_proxy_header()?;
+        let result = if let Some(conn) = &mut self.conn {
+            let handshaking = conn.is_handshaking();
             let mut tls_stream = rustls::Stream::new(conn, &mut self.tcp);
-            tls_stream.read(buf)
+            let result = tls_stream.read(buf);
+            match &result {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) | Ok(0) if handshaking => METRICS.tls_handshake_failed(),
+                _ => {}
+            }
+            result
         } else {
             self.tcp.read(buf)
+        };
+        if let Ok(n) = result {
+            METRICS.add_received(n);
         }
+        result
     }
 }
 
 impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if let Some(conn) = &mut self.conn {
+        let result = if let Some(conn) = &mut self.conn {
             let mut tls_stream = rustls::Stream::new(conn, &mut self.tcp);
             tls_stream.write(buf)
         } else {
             self.tcp.write(buf)
+        };
+        if let Ok(n) = result {
+            METRICS.add_sent(n);
         }
+        result
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
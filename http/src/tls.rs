@@ -0,0 +1,352 @@
+use crate::types::ClientCertificate;
+use crate::{ClientAuthConfig, ClientAuthMode, TlsConfig, TlsVersion};
+use log::warn;
+use rustls::crypto::{CryptoProvider, aws_lc_rs};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{
+    ClientHello, NoServerSessionStorage, ResolvesServerCert, ServerSessionMemoryCache,
+    VerifierBuilderError, WebPkiClientVerifier,
+};
+use rustls::sign::{CertifiedKey, SingleCertAndKey};
+use rustls::{
+    InconsistentKeys, KeyLog, ProtocolVersion, RootCertStore, SupportedCipherSuite,
+    SupportedProtocolVersion, version,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::sync::{Arc, Mutex};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+#[derive(Debug)]
+pub enum TlsError {
+    MissingFile(String),
+    Unreadable(String, io::Error),
+    InvalidPem(String, pem::Error),
+    NoCertificates(String),
+    NoPrivateKey(String),
+    UnsupportedKeyType(String),
+    InvalidCertificate(String, rustls::Error),
+    KeyMismatch(String, String),
+    ClientVerifier(String, VerifierBuilderError),
+    UnknownCipherSuite(String),
+    KeyLog(String, io::Error),
+    Rustls(rustls::Error),
+}
+impl Error for TlsError {}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TlsError::*;
+        match self {
+            MissingFile(fp) => write!(f, "`{}` does not exist", fp),
+            Unreadable(fp, e) => write!(f, "could not read `{}`: {}", fp, e),
+            InvalidPem(fp, e) => write!(f, "`{}` is not valid PEM: {}", fp, e),
+            NoCertificates(fp) => write!(f, "no certificates found in `{}`", fp),
+            NoPrivateKey(fp) => write!(f, "no private key found in `{}`", fp),
+            UnsupportedKeyType(fp) => write!(
+                f,
+                "unsupported private key type in `{}` (expected RSA, ECDSA or Ed25519)",
+                fp
+            ),
+            InvalidCertificate(fp, e) => write!(f, "invalid certificate in `{}`: {}", fp, e),
+            KeyMismatch(cert, key) => write!(
+                f,
+                "private key `{}` does not match certificate `{}`",
+                key, cert
+            ),
+            ClientVerifier(fp, e) => {
+                write!(f, "could not verify clients with `{}`: {}", fp, e)
+            }
+            UnknownCipherSuite(s) => write!(f, "unknown cipher suite `{}`", s),
+            KeyLog(fp, e) => write!(f, "could not open key log `{}`: {}", fp, e),
+            Rustls(e) => write!(f, "tls error: {}", e),
+        }
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> TlsError {
+        TlsError::Rustls(e)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Looks up a cipher suite by its IANA name, e.g. `TLS13_AES_128_GCM_SHA256`.
+pub fn find_cipher_suite(name: &str) -> Option<SupportedCipherSuite> {
+    aws_lc_rs::ALL_CIPHER_SUITES
+        .iter()
+        .find(|s| s.suite().as_str() == Some(name))
+        .copied()
+}
+
+pub fn cipher_suite_version(name: &str) -> Option<TlsVersion> {
+    let suite = find_cipher_suite(name)?;
+    match suite.version().version {
+        ProtocolVersion::TLSv1_3 => Some(TlsVersion::Tls13),
+        _ => Some(TlsVersion::Tls12),
+    }
+}
+
+/// Writes secrets in the NSS key log format understood by Wireshark.
+#[derive(Debug)]
+struct KeyLogWriter {
+    fp: String,
+    file: Mutex<File>,
+}
+
+impl KeyLogWriter {
+    fn open(fp: &str) -> Result<KeyLogWriter, TlsError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(fp)
+            .map_err(|e| TlsError::KeyLog(fp.to_string(), e))?;
+        Ok(KeyLogWriter {
+            fp: fp.to_string(),
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format!("{} {} {}\n", label, hex(client_random), hex(secret));
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("could not write key log `{}`: {}", self.fp, e);
+        }
+    }
+}
+
+fn read_file(fp: &str) -> Result<Vec<u8>, TlsError> {
+    fs::read(fp).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => TlsError::MissingFile(fp.to_string()),
+        _ => TlsError::Unreadable(fp.to_string(), e),
+    })
+}
+
+pub fn load_certs(fp: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let contents = read_file(fp)?;
+    let mut certs = Vec::new();
+    for cert in CertificateDer::pem_slice_iter(&contents) {
+        match cert {
+            Ok(c) => certs.push(c),
+            Err(e) => return Err(TlsError::InvalidPem(fp.to_string(), e)),
+        }
+    }
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(fp.to_string()));
+    }
+    Ok(certs)
+}
+
+pub fn load_private_key(fp: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let contents = read_file(fp)?;
+    match PrivateKeyDer::from_pem_slice(&contents) {
+        Ok(k) => Ok(k),
+        Err(pem::Error::NoItemsFound) => Err(TlsError::NoPrivateKey(fp.to_string())),
+        Err(e) => Err(TlsError::InvalidPem(fp.to_string(), e)),
+    }
+}
+
+fn make_client_verifier(
+    client_auth: &ClientAuthConfig,
+) -> Result<Arc<dyn ClientCertVerifier>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&client_auth.ca)? {
+        if let Err(e) = roots.add(cert) {
+            return Err(TlsError::InvalidCertificate(client_auth.ca.clone(), e));
+        }
+    }
+    let mut builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    if client_auth.mode == ClientAuthMode::Optional {
+        builder = builder.allow_unauthenticated();
+    }
+    builder
+        .build()
+        .map_err(|e| TlsError::ClientVerifier(client_auth.ca.clone(), e))
+}
+
+/// Summarises a verified client certificate for handlers.
+pub fn client_certificate(der: &CertificateDer) -> ClientCertificate {
+    let subject = match X509Certificate::from_der(der) {
+        Ok((_, cert)) => cert.subject().to_string(),
+        Err(e) => {
+            warn!("could not parse client certificate: {}", e);
+            String::new()
+        }
+    };
+    let fingerprint = hex(&Sha256::digest(der));
+    ClientCertificate {
+        subject,
+        fingerprint,
+        der: der.to_vec(),
+    }
+}
+
+fn load_certified_key(
+    provider: &CryptoProvider,
+    cert: &str,
+    key: &str,
+) -> Result<CertifiedKey, TlsError> {
+    let certs = load_certs(cert)?;
+    let private_key = load_private_key(key)?;
+    let signing_key = match provider.key_provider.load_private_key(private_key) {
+        Ok(k) => k,
+        Err(_) => return Err(TlsError::UnsupportedKeyType(key.to_string())),
+    };
+    let certified_key = CertifiedKey::new(certs, signing_key);
+    match certified_key.keys_match() {
+        // rustls can not always tell, so only a definite mismatch is an error
+        Ok(()) | Err(rustls::Error::InconsistentKeys(InconsistentKeys::Unknown)) => {}
+        Err(rustls::Error::InconsistentKeys(InconsistentKeys::KeyMismatch)) => {
+            return Err(TlsError::KeyMismatch(cert.to_string(), key.to_string()));
+        }
+        Err(e) => return Err(TlsError::InvalidCertificate(cert.to_string(), e)),
+    }
+    Ok(certified_key)
+}
+
+/// Picks a certificate by the server name the client asked for.
+#[derive(Debug)]
+struct SniResolver {
+    /// for clients that send no name, or one without its own certificate
+    default: Arc<CertifiedKey>,
+    /// lowercase name to certificate
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()));
+        Some(key.unwrap_or(&self.default).clone())
+    }
+}
+
+pub fn make_tls_config(config: &TlsConfig) -> Result<rustls::ServerConfig, TlsError> {
+    let mut provider = aws_lc_rs::default_provider();
+    if !config.cipher_suites.is_empty() {
+        let mut suites = Vec::new();
+        for name in &config.cipher_suites {
+            match find_cipher_suite(name) {
+                Some(s) => suites.push(s),
+                None => return Err(TlsError::UnknownCipherSuite(name.clone())),
+            }
+        }
+        provider.cipher_suites = suites;
+    }
+    let versions: &[&'static SupportedProtocolVersion] = match config.min_version {
+        TlsVersion::Tls12 => &[&version::TLS13, &version::TLS12],
+        TlsVersion::Tls13 => &[&version::TLS13],
+    };
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)?;
+    let provider = builder.crypto_provider().clone();
+    let certified_key = load_certified_key(&provider, &config.cert, &config.key)?;
+    let mut by_name = HashMap::new();
+    for sni in &config.sni {
+        let key = Arc::new(load_certified_key(&provider, &sni.cert, &sni.key)?);
+        for name in &sni.names {
+            by_name.insert(name.to_ascii_lowercase(), key.clone());
+        }
+    }
+
+    let builder = match &config.client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(make_client_verifier(client_auth)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = if by_name.is_empty() {
+        builder.with_cert_resolver(Arc::new(SingleCertAndKey::from(certified_key)))
+    } else {
+        builder.with_cert_resolver(Arc::new(SniResolver {
+            default: Arc::new(certified_key),
+            by_name,
+        }))
+    };
+    server_config.alpn_protocols = config
+        .alpn_protocols
+        .iter()
+        .map(|p| p.as_bytes().to_vec())
+        .collect();
+    server_config.session_storage = match config.session.cache_size {
+        0 => Arc::new(NoServerSessionStorage {}),
+        n => ServerSessionMemoryCache::new(n),
+    };
+    if config.session.tickets {
+        server_config.ticketer = aws_lc_rs::Ticketer::new()?;
+    }
+    if let Some(fp) = &config.key_log {
+        warn!("logging tls secrets to `{}`", fp);
+        server_config.key_log = Arc::new(KeyLogWriter::open(fp)?);
+    }
+    Ok(server_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SessionConfig;
+    use std::env;
+    use std::process;
+
+    fn config(cert: &str, key: &str) -> TlsConfig {
+        TlsConfig {
+            cert: cert.to_string(),
+            key: key.to_string(),
+            sni: Vec::new(),
+            client_auth: None,
+            min_version: TlsVersion::Tls12,
+            cipher_suites: Vec::new(),
+            alpn_protocols: Vec::new(),
+            session: SessionConfig {
+                tickets: false,
+                cache_size: 0,
+            },
+            key_log: None,
+        }
+    }
+
+    #[test]
+    fn test_tls_errors() {
+        let dir = env::temp_dir().join(format!("tls_test_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let a = rcgen::generate_simple_self_signed(vec!["a.test".to_string()]).unwrap();
+        let b = rcgen::generate_simple_self_signed(vec!["b.test".to_string()]).unwrap();
+        fs::write(path("a.pem"), a.cert.pem()).unwrap();
+        fs::write(path("a.key"), a.signing_key.serialize_pem()).unwrap();
+        fs::write(path("b.key"), b.signing_key.serialize_pem()).unwrap();
+
+        assert!(make_tls_config(&config(&path("a.pem"), &path("a.key"))).is_ok());
+        assert!(matches!(
+            make_tls_config(&config(&path("missing.pem"), &path("a.key"))),
+            Err(TlsError::MissingFile(fp)) if fp == path("missing.pem")
+        ));
+        // a certificate where the key should be, and the other way round
+        assert!(matches!(
+            make_tls_config(&config(&path("a.pem"), &path("a.pem"))),
+            Err(TlsError::NoPrivateKey(fp)) if fp == path("a.pem")
+        ));
+        assert!(matches!(
+            make_tls_config(&config(&path("a.key"), &path("a.key"))),
+            Err(TlsError::NoCertificates(fp)) if fp == path("a.key")
+        ));
+        assert!(matches!(
+            make_tls_config(&config(&path("a.pem"), &path("b.key"))),
+            Err(TlsError::KeyMismatch(cert, key)) if cert == path("a.pem") && key == path("b.key")
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
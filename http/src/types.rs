@@ -1,7 +1,10 @@
+use crate::access_log::AccessLogEntry;
+use crate::metrics::RequestRecord;
 use crate::parser::Future;
 use crate::socket::Stream;
 use std::fmt::Display;
-use std::io::{Error, Write};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
 use std::os::fd::{AsFd, BorrowedFd};
 
 pub type Field = (String, String);
@@ -19,6 +22,48 @@ pub struct HttpRequest {
     pub version: String,
     pub fields: Vec<Field>,
     pub body: Option<Vec<u8>>,
+    /// the client, which differs from the peer behind a trusted proxy
+    pub client_addr: IpAddr,
+    pub scheme: Scheme,
+    /// from `Host`, or as forwarded by a trusted proxy
+    pub host: Option<String>,
+    /// from `X-Request-Id`, or generated; echoed in the response
+    pub id: String,
+    pub connection: ConnectionInfo,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+/// What is known about the connection a request arrived on.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    /// `None` for plaintext connections
+    pub tls: Option<TlsInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    /// server name the client asked for via SNI
+    pub sni: Option<String>,
+    /// negotiated ALPN protocol, e.g. `http/1.1`
+    pub alpn: Option<String>,
+    pub client_cert: Option<ClientCertificate>,
+}
+
+/// A client certificate that was verified against the configured client CA.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    /// distinguished name, e.g. `CN=alice, O=Example`
+    pub subject: String,
+    /// lowercase hex SHA-256 of the DER encoding
+    pub fingerprint: String,
+    pub der: Vec<u8>,
 }
 
 impl Display for Method {
@@ -30,6 +75,72 @@ impl Display for Method {
     }
 }
 
+impl HttpRequest {
+    /// A request for `path` without fields or body, from a local client
+    /// over plain HTTP; the `with_` methods fill in the rest. The parser
+    /// builds its own, so this is mostly for handlers' tests.
+    pub fn new(method: Method, path: &str) -> HttpRequest {
+        let local = SocketAddr::from(([127, 0, 0, 1], 0));
+        HttpRequest {
+            method,
+            path: path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            fields: Vec::new(),
+            body: None,
+            client_addr: local.ip(),
+            scheme: Scheme::Http,
+            host: None,
+            id: String::new(),
+            connection: ConnectionInfo {
+                peer_addr: local,
+                local_addr: local,
+                tls: None,
+            },
+        }
+    }
+
+    pub fn with_field(mut self, name: &str, value: &str) -> HttpRequest {
+        self.fields.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the peer, and the client as no proxy has spoken for it yet.
+    pub fn with_peer(mut self, peer: SocketAddr) -> HttpRequest {
+        self.connection.peer_addr = peer;
+        self.client_addr = peer.ip();
+        self
+    }
+
+    pub fn with_host(mut self, host: &str) -> HttpRequest {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    /// Makes it arrive over TLS.
+    pub fn with_tls(mut self, tls: TlsInfo) -> HttpRequest {
+        self.connection.tls = Some(tls);
+        self.scheme = Scheme::Https;
+        self
+    }
+
+    /// The first value of the field `name`, ignoring case.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Scheme::Http => write!(f, "http"),
+            Scheme::Https => write!(f, "https"),
+        }
+    }
+}
+
 impl Display for HttpRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         write!(f, "{} {} {}", self.method, self.path, self.version)?;
@@ -41,14 +152,55 @@ impl Display for HttpRequest {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum StatusCode {
     OK,
+    PartialContent,
+    NotModified,
     BadRequest,
     Unauthorized,
     NotFound,
+    RangeNotSatisfiable,
+    TooManyRequests,
     InternalError,
     NotImplemented,
+    ServiceUnavailable,
+}
+
+impl StatusCode {
+    pub fn code(&self) -> u16 {
+        use StatusCode::*;
+        match self {
+            OK => 200,
+            PartialContent => 206,
+            NotModified => 304,
+            BadRequest => 400,
+            Unauthorized => 401,
+            NotFound => 404,
+            RangeNotSatisfiable => 416,
+            TooManyRequests => 429,
+            InternalError => 500,
+            NotImplemented => 501,
+            ServiceUnavailable => 503,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        use StatusCode::*;
+        match self {
+            OK => "OK",
+            PartialContent => "PARTIAL CONTENT",
+            NotModified => "NOT MODIFIED",
+            BadRequest => "BAD REQUEST",
+            Unauthorized => "UNAUTHORIZED",
+            NotFound => "NOT FOUND",
+            RangeNotSatisfiable => "RANGE NOT SATISFIABLE",
+            TooManyRequests => "TOO MANY REQUESTS",
+            InternalError => "INTERNAL SERVER ERROR",
+            NotImplemented => "NOT IMPLEMENTED",
+            ServiceUnavailable => "SERVICE UNAVAILABLE",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -63,24 +215,21 @@ pub struct Responder {
     bytes: Vec<u8>,
     sent: usize,
     stream: Stream,
+    log_entry: Option<AccessLogEntry>,
+    record: Option<RequestRecord>,
 }
 
 impl Responder {
-    pub fn as_fd(&self) -> BorrowedFd {
-        return self.stream.as_fd();
+    pub fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
     }
     pub fn from_http_response(r: HttpResponse, stream: Stream) -> Responder {
-        let mut response = r.version;
-        response += " ";
-        use StatusCode::*;
-        response += match r.status_code {
-            OK => "200 OK",
-            NotFound => "404 NOT FOUND",
-            NotImplemented => "501 NOT IMPLEMENTED",
-            BadRequest => "400 BAD REQUEST",
-            Unauthorized => "401 UNAUTHORIZED",
-            InternalError => "500 INTERNAL SERVER ERROR",
-        };
+        let mut response = format!(
+            "{} {} {}",
+            r.version,
+            r.status_code.code(),
+            r.status_code.reason()
+        );
         response += "\r\n";
 
         for (left, right) in r.fields.into_iter() {
@@ -99,13 +248,32 @@ impl Responder {
             bytes,
             sent: 0,
             stream,
+            log_entry: None,
+            record: None,
         }
     }
 
+    pub fn set_record(&mut self, record: RequestRecord) {
+        self.record = Some(record);
+    }
+
+    pub fn take_record(&mut self) -> Option<RequestRecord> {
+        self.record.take()
+    }
+
+    /// Kept until the response has been sent.
+    pub fn set_log_entry(&mut self, entry: AccessLogEntry) {
+        self.log_entry = Some(entry);
+    }
+
+    pub fn take_log_entry(&mut self) -> Option<AccessLogEntry> {
+        self.log_entry.take()
+    }
+
     pub fn respond(&mut self) -> Future<()> {
         let n = match self.stream.write(&self.bytes[self.sent..]) {
             Ok(n) => n,
-            Err(e) => return Future::Fail("there was an error during writing"),
+            Err(_) => return Future::Fail("there was an error during writing"),
         };
         self.sent += n;
         if self.sent == self.bytes.len() {
@@ -121,10 +289,18 @@ impl HttpResponse {
             version: "HTTP/1.1".to_string(),
             status_code: code,
             fields: Vec::new(),
-            body: body,
+            body,
         }
     }
 
+    /// The first value of the field `name`, ignoring case.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
     /*
     pub fn respond(&self, stream: &mut Stream) -> Result<(), Error> {
         let mut response = self.version.clone();